@@ -1,49 +1,95 @@
 use std::fs;
 
-fn get_single_increase_count(mut readings: Vec<u32>) -> u32 {
-    let mut readings = readings.iter_mut();
-
-    let mut previous_reading = readings.next().unwrap();
-    let mut increases = 0;
-
-    for reading in readings {
-        if reading > previous_reading {
-            increases += 1;
-        }
-        previous_reading = reading;
-    }
-    increases
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Comparison {
+    Strict,
+    #[allow(dead_code)]
+    NonStrict,
 }
 
-/// The 3-long sliding increase comparison is equivalent to comparing single values 3 apart.
-fn get_triple_increase_count(readings: Vec<u32>) -> u32 {
-    let reading_windows = readings.windows(4);
-    let mut increases = 0;
+/// Adjacent windows share all but their end readings, so comparing their sums is the same as
+/// comparing the reading that leaves with the one that enters, `window` places apart.
+fn count_window_increases(readings: &[u32], window: usize, comparison: Comparison) -> usize {
+    assert!(window > 0, "Window must contain at least one reading");
 
-    for window in reading_windows {
-        println!("{:?}", window);
-        if window.last() > window.first() {
-            increases += 1;
-        }
-    }
-    increases
+    readings
+        .iter()
+        .zip(readings.iter().skip(window))
+        .filter(|(leaving, entering)| match comparison {
+            Comparison::Strict => entering > leaving,
+            Comparison::NonStrict => entering >= leaving,
+        })
+        .count()
 }
 
 fn main() {
     let filename = "data/day01.txt";
 
     let input = fs::read_to_string(filename).unwrap();
-    let depth_readings: Vec<u32> = input
-        .split("\n")
-        .map(|i| i.parse::<u32>().unwrap())
-        .collect();
+    let depth_readings: Vec<u32> = input.lines().map(|i| i.parse::<u32>().unwrap()).collect();
 
     println!(
         "Single increase count: {}",
-        get_single_increase_count(depth_readings.clone())
+        count_window_increases(&depth_readings, 1, Comparison::Strict)
     );
     println!(
         "Triple increase count: {}",
-        get_triple_increase_count(depth_readings.clone())
+        count_window_increases(&depth_readings, 3, Comparison::Strict)
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE_READINGS: [u32; 10] = [199, 200, 208, 210, 200, 207, 240, 269, 260, 263];
+
+    #[test]
+    fn test_example() {
+        assert_eq!(
+            count_window_increases(&EXAMPLE_READINGS, 1, Comparison::Strict),
+            7
+        );
+        assert_eq!(
+            count_window_increases(&EXAMPLE_READINGS, 3, Comparison::Strict),
+            5
+        );
+    }
+
+    #[test]
+    fn test_matches_window_sums() {
+        for window in 1..=EXAMPLE_READINGS.len() + 1 {
+            let sums: Vec<u32> = EXAMPLE_READINGS
+                .windows(window)
+                .map(|w| w.iter().sum())
+                .collect();
+
+            assert_eq!(
+                count_window_increases(&EXAMPLE_READINGS, window, Comparison::Strict),
+                sums.windows(2).filter(|pair| pair[1] > pair[0]).count()
+            );
+        }
+    }
+
+    #[test]
+    fn test_non_strict() {
+        let readings = [1, 1, 2, 1, 1];
+
+        assert_eq!(count_window_increases(&readings, 1, Comparison::Strict), 1);
+        assert_eq!(
+            count_window_increases(&readings, 1, Comparison::NonStrict),
+            3
+        );
+        assert_eq!(
+            count_window_increases(&readings, 2, Comparison::NonStrict),
+            2
+        );
+    }
+
+    #[test]
+    fn test_short_input() {
+        assert_eq!(count_window_increases(&[], 1, Comparison::Strict), 0);
+        assert_eq!(count_window_increases(&[5], 1, Comparison::NonStrict), 0);
+        assert_eq!(count_window_increases(&[1, 2, 3], 3, Comparison::Strict), 0);
+    }
+}
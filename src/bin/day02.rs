@@ -1,84 +1,305 @@
+use lazy_static::lazy_static;
 use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::Debug;
 
-#[derive(Debug, PartialEq)]
-enum Direction {
-    Forward,
-    Down,
-    Up,
+lazy_static! {
+    static ref INSTRUCTION_REGEX: Regex = Regex::new(r"^([a-z-]+)(?: (\d+))?$").unwrap();
 }
 
 type Position = (i32, i32);
 
-type Instruction = (Direction, i32);
+/// How `down` and `up` are interpreted: either they change depth directly, or they change the aim
+/// and `forward` then moves along it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum MovementModel {
+    Direct,
+    Aimed,
+}
 
-fn parse_route(input: &str) -> Vec<Instruction> {
-    let re = Regex::new(r"([a-z]+) (\d+)").unwrap();
+#[derive(Debug, PartialEq, Clone)]
+struct Submarine {
+    model: MovementModel,
+    position: Position,
+    aim: i32,
+}
 
-    let raw_instructions = input.split("\n").map(|s| {
-        let captures = re.captures(s).unwrap();
+impl Submarine {
+    fn new(model: MovementModel) -> Self {
+        Submarine {
+            model,
+            position: (0, 0),
+            aim: 0,
+        }
+    }
 
-        (
-            match &captures[1] {
-                "forward" => Direction::Forward,
-                "down" => Direction::Down,
-                "up" => Direction::Up,
-                _ => panic!("Invalid direction: {}", s),
-            },
-            captures[2].parse::<i32>().unwrap(),
-        )
-    });
+    fn advance(&mut self, distance: i32) {
+        self.position.0 += distance;
 
-    raw_instructions.collect()
-}
+        if self.model == MovementModel::Aimed {
+            self.position.1 += self.aim * distance;
+        }
+    }
+
+    /// Positive is downwards.
+    fn dive(&mut self, change: i32) {
+        match self.model {
+            MovementModel::Direct => self.position.1 += change,
+            MovementModel::Aimed => self.aim += change,
+        }
+    }
 
-fn follow_route(instructions: Vec<Instruction>) -> Position {
-    let mut position: Position = (0, 0);
+    fn execute(&mut self, command: &dyn Command) {
+        command.execute(self);
+    }
 
-    for (direction, distance) in instructions {
-        match direction {
-            Direction::Forward => position.0 += distance,
-            Direction::Down => position.1 += distance,
-            Direction::Up => position.1 -= distance,
+    fn run(mut self, route: &[Box<dyn Command>]) -> Self {
+        for command in route {
+            self.execute(command.as_ref());
         }
+
+        self
     }
 
-    position
-}
+    /// The state after each command, not including the starting state.
+    fn trace(self, route: &[Box<dyn Command>]) -> Trace<'_> {
+        Trace {
+            submarine: self,
+            commands: route.iter(),
+        }
+    }
 
-fn follow_aimed_route(instructions: Vec<Instruction>) -> Position {
-    let mut position: Position = (0, 0);
-    let mut aim = 0;
+    /// The total distance is the Manhattan length of the path, summed over each command's move. An
+    /// aimed `forward` moves diagonally, so it counts as its horizontal plus its vertical distance.
+    fn analyse(self, route: &Route, enforce_surface: bool) -> Result<RouteStats, SurfaceViolation> {
+        let start = self.position;
+        let mut stats = RouteStats {
+            max_depth: start.1,
+            total_distance: 0,
+            bounding_box: (start, start),
+        };
+        let mut previous = start;
 
-    for (direction, distance) in instructions {
-        match direction {
-            Direction::Forward => {
-                position.0 += distance;
-                position.1 += aim * distance;
+        for (&line_number, submarine) in route.line_numbers.iter().zip(self.trace(&route.commands))
+        {
+            let (x, depth) = submarine.position;
+
+            if enforce_surface && depth < 0 {
+                return Err(SurfaceViolation {
+                    line_number,
+                    submarine,
+                });
             }
-            Direction::Down => aim += distance,
-            Direction::Up => aim -= distance,
+
+            stats.max_depth = stats.max_depth.max(depth);
+            stats.total_distance += (x - previous.0).abs() + (depth - previous.1).abs();
+
+            let ((min_x, min_depth), (max_x, max_depth)) = &mut stats.bounding_box;
+            *min_x = (*min_x).min(x);
+            *max_x = (*max_x).max(x);
+            *min_depth = (*min_depth).min(depth);
+            *max_depth = (*max_depth).max(depth);
+
+            previous = submarine.position;
         }
+
+        Ok(stats)
     }
+}
+
+struct Trace<'a> {
+    submarine: Submarine,
+    commands: std::slice::Iter<'a, Box<dyn Command>>,
+}
+
+impl Iterator for Trace<'_> {
+    type Item = Submarine;
+
+    fn next(&mut self) -> Option<Submarine> {
+        let command = self.commands.next()?;
+        self.submarine.execute(command.as_ref());
+
+        Some(self.submarine.clone())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct RouteStats {
+    max_depth: i32,
+    total_distance: i32,
+    /// The minimum and maximum corners, including the starting position.
+    bounding_box: (Position, Position),
+}
+
+/// The submarine went above the surface after the command on the given line of the route.
+#[derive(Debug, PartialEq)]
+struct SurfaceViolation {
+    line_number: usize,
+    submarine: Submarine,
+}
+
+/// A single instruction. New kinds of instruction only need to implement this and be registered
+/// with a `CommandSet`.
+trait Command: Debug {
+    fn execute(&self, submarine: &mut Submarine);
+}
+
+#[derive(Debug)]
+struct Forward(i32);
+
+impl Command for Forward {
+    fn execute(&self, submarine: &mut Submarine) {
+        submarine.advance(self.0);
+    }
+}
+
+#[derive(Debug)]
+struct Down(i32);
+
+impl Command for Down {
+    fn execute(&self, submarine: &mut Submarine) {
+        submarine.dive(self.0);
+    }
+}
+
+#[derive(Debug)]
+struct Up(i32);
+
+impl Command for Up {
+    fn execute(&self, submarine: &mut Submarine) {
+        submarine.dive(-self.0);
+    }
+}
+
+/// Parsed commands, along with the line each one came from.
+#[derive(Debug)]
+struct Route {
+    commands: Vec<Box<dyn Command>>,
+    line_numbers: Vec<usize>,
+}
+
+/// Builds a command from its operand, if it takes one.
+enum CommandConstructor {
+    WithOperand(fn(i32) -> Box<dyn Command>),
+    WithoutOperand(fn() -> Box<dyn Command>),
+}
+
+/// The commands the parser recognises, by name.
+struct CommandSet(HashMap<&'static str, CommandConstructor>);
+
+impl CommandSet {
+    fn standard() -> Self {
+        CommandSet(HashMap::new())
+            .with("forward", |n| Box::new(Forward(n)))
+            .with("down", |n| Box::new(Down(n)))
+            .with("up", |n| Box::new(Up(n)))
+    }
+
+    fn with(mut self, name: &'static str, constructor: fn(i32) -> Box<dyn Command>) -> Self {
+        self.0
+            .insert(name, CommandConstructor::WithOperand(constructor));
+        self
+    }
+
+    #[allow(dead_code)]
+    fn with_no_operand(
+        mut self,
+        name: &'static str,
+        constructor: fn() -> Box<dyn Command>,
+    ) -> Self {
+        self.0
+            .insert(name, CommandConstructor::WithoutOperand(constructor));
+        self
+    }
+
+    /// Blank lines are skipped, but still count towards line numbers.
+    fn parse_route(&self, input: &str) -> Result<Route, RouteParseError> {
+        let (line_numbers, commands) = input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                let line_number = i + 1;
+                let malformed = || RouteParseError::Malformed {
+                    line_number,
+                    line: line.to_string(),
+                };
+
+                let captures = INSTRUCTION_REGEX
+                    .captures(line.trim())
+                    .ok_or_else(malformed)?;
+                let constructor =
+                    self.0
+                        .get(&captures[1])
+                        .ok_or_else(|| RouteParseError::UnknownCommand {
+                            line_number,
+                            name: captures[1].to_string(),
+                        })?;
+
+                match (constructor, captures.get(2)) {
+                    (CommandConstructor::WithOperand(constructor), Some(operand)) => {
+                        let operand = operand.as_str().parse().map_err(|_| malformed())?;
+                        Ok((line_number, constructor(operand)))
+                    }
+                    (CommandConstructor::WithoutOperand(constructor), None) => {
+                        Ok((line_number, constructor()))
+                    }
+                    _ => Err(malformed()),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .unzip();
+
+        Ok(Route {
+            commands,
+            line_numbers,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum RouteParseError {
+    Malformed { line_number: usize, line: String },
+    UnknownCommand { line_number: usize, name: String },
+}
 
-    position
+fn parse_route(input: &str) -> Result<Route, RouteParseError> {
+    CommandSet::standard().parse_route(input)
+}
+
+fn follow_route(route: &[Box<dyn Command>]) -> Position {
+    Submarine::new(MovementModel::Direct).run(route).position
+}
+
+fn follow_aimed_route(route: &[Box<dyn Command>]) -> Position {
+    Submarine::new(MovementModel::Aimed).run(route).position
 }
 
 fn main() {
     let input = std::fs::read_to_string("data/day02.txt").unwrap();
+    let route = parse_route(&input).unwrap();
 
-    let final_position = follow_route(parse_route(&input));
+    let final_position = follow_route(&route.commands);
     println!(
         "Final position: {:?} => {}",
         final_position,
         final_position.0 * final_position.1
     );
 
-    let final_aimed_position = follow_aimed_route(parse_route(&input));
+    let final_aimed_position = follow_aimed_route(&route.commands);
     println!(
         "Final aimed position: {:?} => {}",
         final_aimed_position,
         final_aimed_position.0 * final_aimed_position.1
     );
+
+    for model in [MovementModel::Direct, MovementModel::Aimed] {
+        match Submarine::new(model).analyse(&route, true) {
+            Ok(stats) => println!("{:?} route: {:?}", model, stats),
+            Err(violation) => println!("{:?} route surfaces: {:?}", model, violation),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -87,35 +308,186 @@ mod tests {
 
     #[test]
     fn test_follow_route() {
-        assert_eq!(follow_route(vec![]), (0, 0));
+        assert_eq!(follow_route(&[]), (0, 0));
         assert_eq!(
-            follow_route(vec![(Direction::Forward, 1), (Direction::Down, 2)]),
+            follow_route(&[Box::new(Forward(1)), Box::new(Down(2))]),
             (1, 2)
         );
         assert_eq!(
-            follow_route(vec![(Direction::Forward, 1), (Direction::Up, 2)]),
+            follow_route(&[Box::new(Forward(1)), Box::new(Up(2))]),
             (1, -2)
         );
     }
 
     #[test]
     fn test_follow_aimed_route() {
-        assert_eq!(follow_aimed_route(vec![]), (0, 0));
-        assert_eq!(follow_aimed_route(vec![(Direction::Forward, 1)]), (1, 0));
-        assert_eq!(follow_aimed_route(vec![(Direction::Up, 1)]), (0, 0));
-        assert_eq!(follow_aimed_route(vec![(Direction::Down, 1)]), (0, 0));
+        assert_eq!(follow_aimed_route(&[]), (0, 0));
+        assert_eq!(follow_aimed_route(&[Box::new(Forward(1))]), (1, 0));
+        assert_eq!(follow_aimed_route(&[Box::new(Up(1))]), (0, 0));
+        assert_eq!(follow_aimed_route(&[Box::new(Down(1))]), (0, 0));
         assert_eq!(
-            follow_aimed_route(vec![(Direction::Down, 2), (Direction::Forward, 3)]),
+            follow_aimed_route(&[Box::new(Down(2)), Box::new(Forward(3))]),
             (3, 6)
         );
         assert_eq!(
-            follow_aimed_route(vec![(Direction::Up, 2), (Direction::Forward, 3)]),
+            follow_aimed_route(&[Box::new(Up(2)), Box::new(Forward(3))]),
             (3, -6)
         );
     }
 
     #[test]
     fn test_parse_input() {
-        assert_eq!(parse_route("down 1"), vec![(Direction::Down, 1)]);
+        assert_eq!(
+            format!("{:?}", parse_route("down 1").unwrap().commands),
+            "[Down(1)]"
+        );
+
+        let route = parse_route("forward 5\n\nup 3\n").unwrap();
+        assert_eq!(format!("{:?}", route.commands), "[Forward(5), Up(3)]");
+        assert_eq!(route.line_numbers, [1, 3]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_route("down 1\nsideways 2").unwrap_err(),
+            RouteParseError::UnknownCommand {
+                line_number: 2,
+                name: "sideways".to_string()
+            }
+        );
+        assert_eq!(
+            parse_route("down 1\n\nforward\n").unwrap_err(),
+            RouteParseError::Malformed {
+                line_number: 3,
+                line: "forward".to_string()
+            }
+        );
+        assert_eq!(
+            parse_route("up 99999999999").unwrap_err(),
+            RouteParseError::Malformed {
+                line_number: 1,
+                line: "up 99999999999".to_string()
+            }
+        );
+    }
+
+    #[derive(Debug)]
+    struct Back(i32);
+
+    impl Command for Back {
+        fn execute(&self, submarine: &mut Submarine) {
+            submarine.advance(-self.0);
+        }
+    }
+
+    #[derive(Debug)]
+    struct ResetAim;
+
+    impl Command for ResetAim {
+        fn execute(&self, submarine: &mut Submarine) {
+            submarine.aim = 0;
+        }
+    }
+
+    #[test]
+    fn test_extended_commands() {
+        let commands = CommandSet::standard()
+            .with("back", |n| Box::new(Back(n)))
+            .with_no_operand("reset-aim", || Box::new(ResetAim));
+
+        let route = commands
+            .parse_route("down 2\nforward 3\nreset-aim\nback 1")
+            .unwrap();
+
+        assert_eq!(follow_route(&route.commands), (2, 2));
+        assert_eq!(follow_aimed_route(&route.commands), (2, 6));
+
+        assert_eq!(
+            commands.parse_route("reset-aim 0").unwrap_err(),
+            RouteParseError::Malformed {
+                line_number: 1,
+                line: "reset-aim 0".to_string()
+            }
+        );
+        assert_eq!(
+            commands.parse_route("back").unwrap_err(),
+            RouteParseError::Malformed {
+                line_number: 1,
+                line: "back".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_example() {
+        let route = parse_route("forward 5\ndown 5\nforward 8\nup 3\ndown 8\nforward 2").unwrap();
+
+        assert_eq!(follow_route(&route.commands), (15, 10));
+        assert_eq!(follow_aimed_route(&route.commands), (15, 60));
+    }
+
+    #[test]
+    fn test_trace() {
+        let route = parse_route("down 2\nforward 3\nup 1").unwrap();
+
+        assert_eq!(
+            Submarine::new(MovementModel::Aimed)
+                .trace(&route.commands)
+                .map(|submarine| (submarine.position, submarine.aim))
+                .collect::<Vec<_>>(),
+            [((0, 0), 2), ((3, 6), 2), ((3, 6), 1)]
+        );
+        assert_eq!(Submarine::new(MovementModel::Direct).trace(&[]).count(), 0);
+    }
+
+    #[test]
+    fn test_analyse() {
+        let route = parse_route("forward 5\ndown 5\nforward 8\nup 3\ndown 8\nforward 2").unwrap();
+
+        assert_eq!(
+            Submarine::new(MovementModel::Direct).analyse(&route, true),
+            Ok(RouteStats {
+                max_depth: 10,
+                total_distance: 31,
+                bounding_box: ((0, 0), (15, 10))
+            })
+        );
+        assert_eq!(
+            Submarine::new(MovementModel::Aimed).analyse(&route, true),
+            Ok(RouteStats {
+                max_depth: 60,
+                total_distance: 15 + 60,
+                bounding_box: ((0, 0), (15, 60))
+            })
+        );
+    }
+
+    #[test]
+    fn test_surface_constraint() {
+        let route = parse_route("forward 1\n\nup 2\ndown 3\nup 4").unwrap();
+
+        assert_eq!(
+            Submarine::new(MovementModel::Direct).analyse(&route, true),
+            Err(SurfaceViolation {
+                line_number: 3,
+                submarine: Submarine {
+                    model: MovementModel::Direct,
+                    position: (1, -2),
+                    aim: 0
+                }
+            })
+        );
+        assert_eq!(
+            Submarine::new(MovementModel::Direct).analyse(&route, false),
+            Ok(RouteStats {
+                max_depth: 1,
+                total_distance: 10,
+                bounding_box: ((0, -3), (1, 1))
+            })
+        );
+        assert!(Submarine::new(MovementModel::Aimed)
+            .analyse(&route, true)
+            .is_ok());
     }
 }
@@ -1,119 +1,305 @@
+/// Each report is packed into a `u64`, with the first character of the line as the most significant bit.
 #[derive(PartialEq, Debug, Clone)]
-struct Report<const L: usize>([bool; L]);
-
-fn parse_reports<const L: usize>(input: &str) -> Vec<Report<L>> {
-    input
-        .split("\n")
-        .map(|s| {
-            Report(
-                s.chars()
-                    .take(L)
-                    .map(|c| c == '1')
-                    .collect::<Vec<_>>()
-                    .try_into()
-                    .unwrap(),
-            )
-        })
-        .collect()
+struct Reports {
+    width: usize,
+    values: Vec<u64>,
 }
 
-fn summarise<const L: usize>(reports: Vec<Report<L>>) -> [u32; L] {
-    reports.iter().fold([0; L], |mut acc, r| {
-        for i in 0..L {
-            acc[i] += r.0[i] as u32;
-        }
-        acc
-    })
-}
+impl Reports {
+    /// Positions count from the left of the line, as they're written.
+    fn mask(&self, position: usize) -> u64 {
+        1 << (self.width - 1 - position)
+    }
 
-fn calculate_e_g<const L: usize>(reports: Vec<Report<L>>) -> (u32, u32) {
-    let report_count = reports.len().try_into().unwrap();
-    let summary = summarise(reports);
+    fn all_bits(&self) -> u64 {
+        u64::MAX >> (64 - self.width)
+    }
+}
 
-    let most_common_bits: Vec<bool> = summary
-        .into_iter()
-        .map(|total| total * 2 >= report_count)
-        .collect();
+#[derive(PartialEq, Debug)]
+enum ReportParseError {
+    Empty,
+    TooWide(usize),
+    WrongWidth {
+        line_number: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidCharacter {
+        line_number: usize,
+        column: usize,
+        found: char,
+    },
+}
 
-    let epsilon = bits_to_u32(&most_common_bits);
-    let gamma = 2u32.pow(L as u32) - 1 - epsilon;
+fn parse_reports(input: &str) -> Result<Reports, ReportParseError> {
+    let lines: Vec<&str> = input.lines().collect();
 
-    (epsilon, gamma)
-}
+    let width = lines.first().map_or(0, |line| line.chars().count());
+    if width == 0 {
+        return Err(ReportParseError::Empty);
+    }
+    if width > 64 {
+        return Err(ReportParseError::TooWide(width));
+    }
 
-fn bits_to_u32(bits: &[bool]) -> u32 {
-    bits.iter()
-        .zip((0..bits.len()).rev())
-        .fold(0, |total, (bit, order)| {
-            total + (*bit as u32) * 2u32.pow(order as u32)
+    let values = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let found = line.chars().count();
+            if found != width {
+                return Err(ReportParseError::WrongWidth {
+                    line_number: i + 1,
+                    expected: width,
+                    found,
+                });
+            }
+
+            line.chars()
+                .enumerate()
+                .try_fold(0, |value, (column, c)| match c {
+                    '0' => Ok(value << 1),
+                    '1' => Ok(value << 1 | 1),
+                    found => Err(ReportParseError::InvalidCharacter {
+                        line_number: i + 1,
+                        column,
+                        found,
+                    }),
+                })
         })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Reports { width, values })
 }
 
-fn filter_most_common_value_for_position<const L: usize>(
-    reports: Vec<Report<L>>,
-    position: usize,
-) -> Vec<Report<L>> {
-    let total_for_column = reports
-        .iter()
-        .fold(0, |acc, report| acc + report.0[position] as u32);
-    let most_common_value = total_for_column * 2 >= reports.len() as u32;
+fn count_ones(reports: &Reports, position: usize) -> u32 {
+    let mask = reports.mask(position);
 
     reports
-        .into_iter()
-        .filter(|report| report.0[position] == most_common_value)
+        .values
+        .iter()
+        .filter(|&&value| value & mask != 0)
+        .count() as u32
+}
+
+fn summarise(reports: &Reports) -> Vec<u32> {
+    (0..reports.width)
+        .map(|position| count_ones(reports, position))
         .collect()
 }
 
-fn filter_least_common_value_for_position<const L: usize>(
-    reports: Vec<Report<L>>,
-    position: usize,
-) -> Vec<Report<L>> {
-    let total_for_column = reports
-        .iter()
-        .fold(0, |acc, report| acc + report.0[position] as u32);
-    let most_common_value = total_for_column * 2 >= reports.len() as u32;
+fn calculate_e_g(reports: &Reports) -> (u64, u64) {
+    let report_count = reports.values.len() as u32;
 
-    reports
+    let epsilon = summarise(reports)
         .into_iter()
-        .filter(|report| report.0[position] != most_common_value)
-        .collect()
+        .enumerate()
+        .filter(|&(_, total)| total * 2 >= report_count)
+        .fold(0, |epsilon, (position, _)| epsilon | reports.mask(position));
+    let gamma = !epsilon & reports.all_bits();
+
+    (epsilon, gamma)
 }
 
-fn get_o2_generator_rating<const L: usize>(reports: Vec<Report<L>>) -> u32 {
-    let mut reports = reports;
-    for i in 0..L {
-        reports = filter_most_common_value_for_position(reports, i);
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Commonness {
+    Most,
+    Least,
+}
 
-        if reports.len() == 1 {
-            break;
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum BitOrder {
+    MsbFirst,
+    #[allow(dead_code)]
+    LsbFirst,
+}
+
+/// Decides which bit value survives at each position while narrowing the reports down to a rating.
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct BitCriterion {
+    keep: Commonness,
+    /// The bit value kept when ones and zeros are equally common.
+    tie_break: bool,
+    bit_order: BitOrder,
+}
+
+impl BitCriterion {
+    fn o2_generator() -> Self {
+        BitCriterion {
+            keep: Commonness::Most,
+            tie_break: true,
+            bit_order: BitOrder::MsbFirst,
+        }
+    }
+
+    fn co2_scrubber() -> Self {
+        BitCriterion {
+            keep: Commonness::Least,
+            tie_break: false,
+            bit_order: BitOrder::MsbFirst,
         }
     }
 
-    bits_to_u32(&reports[0].0)
+    fn bit_to_keep(&self, ones: usize, zeros: usize) -> bool {
+        if ones == zeros {
+            return self.tie_break;
+        }
+
+        match self.keep {
+            Commonness::Most => ones > zeros,
+            Commonness::Least => ones < zeros,
+        }
+    }
+}
+
+/// One round of filtering, counted over the reports that were still candidates.
+#[derive(PartialEq, Debug, Clone)]
+struct FilterStep {
+    position: usize,
+    ones: usize,
+    zeros: usize,
+    kept_bit: bool,
+}
+
+#[derive(PartialEq, Debug)]
+struct Rating {
+    value: u64,
+    trace: Vec<FilterStep>,
+}
+
+#[derive(PartialEq, Debug)]
+enum RatingError {
+    NoReports,
+    /// Every remaining report was filtered out; the trace ends with the step that removed them.
+    Emptied(Vec<FilterStep>),
+}
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    /// The number of reports that pass through this node.
+    count: usize,
+    children: [Option<usize>; 2],
+}
+
+/// A binary trie over the reports, branching on their bits in the given order. Each node counts the
+/// reports below it, so a rating is a single walk from the root rather than repeated filtering.
+struct ReportTrie {
+    width: usize,
+    bit_order: BitOrder,
+    nodes: Vec<TrieNode>,
 }
 
-fn get_co2_scrubber_rating<const L: usize>(reports: Vec<Report<L>>) -> u32 {
-    let mut reports = reports;
-    for i in 0..L {
-        reports = filter_least_common_value_for_position(reports, i);
+impl ReportTrie {
+    fn new(reports: &Reports, bit_order: BitOrder) -> Self {
+        let mut trie = ReportTrie {
+            width: reports.width,
+            bit_order,
+            nodes: vec![TrieNode::default()],
+        };
+        let positions = trie.positions();
+
+        for &value in &reports.values {
+            let mut node = 0;
+            trie.nodes[node].count += 1;
+
+            for &position in &positions {
+                let bit = (value & reports.mask(position) != 0) as usize;
+
+                node = match trie.nodes[node].children[bit] {
+                    Some(child) => child,
+                    None => {
+                        trie.nodes.push(TrieNode::default());
+                        let child = trie.nodes.len() - 1;
+                        trie.nodes[node].children[bit] = Some(child);
+                        child
+                    }
+                };
+                trie.nodes[node].count += 1;
+            }
+        }
+
+        trie
+    }
 
-        if reports.len() == 1 {
-            break;
+    fn positions(&self) -> Vec<usize> {
+        match self.bit_order {
+            BitOrder::MsbFirst => (0..self.width).collect(),
+            BitOrder::LsbFirst => (0..self.width).rev().collect(),
         }
     }
 
-    bits_to_u32(&reports[0].0)
+    fn count(&self, child: Option<usize>) -> usize {
+        child.map_or(0, |child| self.nodes[child].count)
+    }
+
+    /// Filtering stops as soon as a single report remains, and the walk just follows that report down
+    /// to recover its value. If several identical reports survive every position, that shared value is
+    /// the rating.
+    fn rate(&self, criterion: BitCriterion) -> Result<Rating, RatingError> {
+        assert_eq!(
+            criterion.bit_order, self.bit_order,
+            "Trie was built for a different bit order"
+        );
+
+        if self.nodes[0].count == 0 {
+            return Err(RatingError::NoReports);
+        }
+
+        let mut node = 0;
+        let mut value = 0;
+        let mut trace = Vec::new();
+
+        for position in self.positions() {
+            let [zeros, ones] = self.nodes[node].children.map(|child| self.count(child));
+
+            let kept_bit = if self.nodes[node].count == 1 {
+                ones == 1
+            } else {
+                let kept_bit = criterion.bit_to_keep(ones, zeros);
+                trace.push(FilterStep {
+                    position,
+                    ones,
+                    zeros,
+                    kept_bit,
+                });
+                kept_bit
+            };
+
+            node = match self.nodes[node].children[kept_bit as usize] {
+                Some(child) => child,
+                None => return Err(RatingError::Emptied(trace)),
+            };
+            if kept_bit {
+                value |= 1 << (self.width - 1 - position);
+            }
+        }
+
+        Ok(Rating { value, trace })
+    }
+}
+
+fn get_o2_generator_rating(trie: &ReportTrie) -> Result<u64, RatingError> {
+    trie.rate(BitCriterion::o2_generator())
+        .map(|rating| rating.value)
+}
+
+fn get_co2_scrubber_rating(trie: &ReportTrie) -> Result<u64, RatingError> {
+    trie.rate(BitCriterion::co2_scrubber())
+        .map(|rating| rating.value)
 }
 
 fn main() {
     let input = std::fs::read_to_string("data/day03.txt").unwrap();
-    let reports = parse_reports::<12>(&input);
+    let reports = parse_reports(&input).unwrap();
 
-    let (epsilon, gamma) = calculate_e_g(reports.clone());
+    let (epsilon, gamma) = calculate_e_g(&reports);
     println!("{:?}", epsilon * gamma);
 
-    let o2 = get_o2_generator_rating(reports.clone());
-    let co2 = get_co2_scrubber_rating(reports);
+    // Both ratings walk the same trie, so it's only built once.
+    let trie = ReportTrie::new(&reports, BitOrder::MsbFirst);
+    let o2 = get_o2_generator_rating(&trie).unwrap();
+    let co2 = get_co2_scrubber_rating(&trie).unwrap();
     println!("{:?}", o2 * co2);
 }
 
@@ -134,104 +320,302 @@ mod tests {
 00010
 01010";
 
-    fn get_test_reports() -> Vec<Report<5>> {
-        parse_reports::<5>(&RAW_TEST_REPORT)
+    fn rate(reports: &Reports, criterion: BitCriterion) -> Result<Rating, RatingError> {
+        ReportTrie::new(reports, criterion.bit_order).rate(criterion)
+    }
+
+    /// A straightforward version of `rate` that filters a copy of the reports one position at a time,
+    /// following the puzzle's description directly.
+    fn rate_by_filtering(
+        reports: &Reports,
+        criterion: BitCriterion,
+    ) -> Result<Rating, RatingError> {
+        let mut candidates = reports.values.clone();
+        let mut trace = Vec::new();
+
+        if candidates.is_empty() {
+            return Err(RatingError::NoReports);
+        }
+
+        let positions: Vec<usize> = match criterion.bit_order {
+            BitOrder::MsbFirst => (0..reports.width).collect(),
+            BitOrder::LsbFirst => (0..reports.width).rev().collect(),
+        };
+
+        for position in positions {
+            if candidates.len() == 1 {
+                break;
+            }
+
+            let mask = reports.mask(position);
+            let ones = candidates
+                .iter()
+                .filter(|&&value| value & mask != 0)
+                .count();
+            let zeros = candidates.len() - ones;
+            let kept_bit = criterion.bit_to_keep(ones, zeros);
+
+            candidates.retain(|&value| (value & mask != 0) == kept_bit);
+            trace.push(FilterStep {
+                position,
+                ones,
+                zeros,
+                kept_bit,
+            });
+
+            if candidates.is_empty() {
+                return Err(RatingError::Emptied(trace));
+            }
+        }
+
+        Ok(Rating {
+            value: candidates[0],
+            trace,
+        })
+    }
+
+    fn get_test_reports() -> Reports {
+        parse_reports(RAW_TEST_REPORT).unwrap()
     }
 
     #[test]
     fn test_power_report() {
-        assert_eq!(calculate_e_g::<5>(get_test_reports()), (22, 9));
+        assert_eq!(calculate_e_g(&get_test_reports()), (22, 9));
     }
 
     #[test]
     fn test_most_common() {
         assert_eq!(
-            filter_most_common_value_for_position(
-                vec![Report([true, false]), Report([false, false])],
-                0
+            rate(
+                &Reports {
+                    width: 2,
+                    values: vec![0b10, 0b00, 0b11]
+                },
+                BitCriterion::o2_generator()
             ),
-            vec![Report([true, false])]
+            Ok(Rating {
+                value: 0b11,
+                trace: vec![
+                    FilterStep {
+                        position: 0,
+                        ones: 2,
+                        zeros: 1,
+                        kept_bit: true
+                    },
+                    FilterStep {
+                        position: 1,
+                        ones: 1,
+                        zeros: 1,
+                        kept_bit: true
+                    }
+                ]
+            })
         );
     }
 
     #[test]
     fn test_o2_generator() {
-        assert_eq!(get_o2_generator_rating(get_test_reports()), 23);
+        assert_eq!(
+            get_o2_generator_rating(&ReportTrie::new(&get_test_reports(), BitOrder::MsbFirst)),
+            Ok(23)
+        );
     }
 
     #[test]
     fn test_least_common() {
         assert_eq!(
-            filter_least_common_value_for_position(
-                vec![Report([true, false]), Report([false, false])],
-                0
+            rate(
+                &Reports {
+                    width: 2,
+                    values: vec![0b10, 0b00, 0b11]
+                },
+                BitCriterion::co2_scrubber()
+            ),
+            Ok(Rating {
+                value: 0b00,
+                trace: vec![FilterStep {
+                    position: 0,
+                    ones: 2,
+                    zeros: 1,
+                    kept_bit: false
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_lsb_first() {
+        let criterion = BitCriterion {
+            bit_order: BitOrder::LsbFirst,
+            ..BitCriterion::o2_generator()
+        };
+
+        assert_eq!(
+            rate(&get_test_reports(), criterion).map(|rating| rating.value),
+            Ok(0b11110)
+        );
+    }
+
+    #[test]
+    fn test_tie_break() {
+        let reports = Reports {
+            width: 1,
+            values: vec![0b1, 0b0],
+        };
+        let criterion = BitCriterion {
+            tie_break: false,
+            ..BitCriterion::o2_generator()
+        };
+
+        assert_eq!(rate(&reports, criterion).map(|rating| rating.value), Ok(0));
+    }
+
+    #[test]
+    fn test_filter_empties_reports() {
+        let reports = Reports {
+            width: 2,
+            values: vec![0b01, 0b01],
+        };
+
+        assert_eq!(
+            rate(&reports, BitCriterion::co2_scrubber()),
+            Err(RatingError::Emptied(vec![FilterStep {
+                position: 0,
+                ones: 0,
+                zeros: 2,
+                kept_bit: true
+            }]))
+        );
+        assert_eq!(
+            rate(&reports, BitCriterion::o2_generator()).map(|rating| rating.value),
+            Ok(0b01)
+        );
+        assert_eq!(
+            rate(
+                &Reports {
+                    width: 2,
+                    values: vec![]
+                },
+                BitCriterion::o2_generator()
             ),
-            vec![Report([false, false])]
+            Err(RatingError::NoReports)
         );
     }
 
     #[test]
     fn test_co2_scrubber() {
-        assert_eq!(get_co2_scrubber_rating(get_test_reports()), 10);
+        assert_eq!(
+            get_co2_scrubber_rating(&ReportTrie::new(&get_test_reports(), BitOrder::MsbFirst)),
+            Ok(10)
+        );
     }
 
     #[test]
     fn test_parse() {
         assert_eq!(
             parse_reports("000000000100"),
-            vec![Report([
-                false, false, false, false, false, false, false, false, false, true, false, false
-            ])]
+            Ok(Reports {
+                width: 12,
+                values: vec![0b000000000100]
+            })
+        );
+        assert_eq!(
+            parse_reports("011\n110\n"),
+            Ok(Reports {
+                width: 3,
+                values: vec![0b011, 0b110]
+            })
         );
     }
 
     #[test]
-    fn test_sumarise() {
+    fn test_parse_errors() {
+        assert_eq!(parse_reports(""), Err(ReportParseError::Empty));
         assert_eq!(
-            summarise(vec![
-                Report([
-                    false, false, false, false, false, false, false, false, false, true, false,
-                    false
-                ]),
-                Report([
-                    false, false, false, false, false, false, false, false, false, true, false,
-                    false
-                ])
-            ]),
-            [0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0]
+            parse_reports(&"1".repeat(65)),
+            Err(ReportParseError::TooWide(65))
+        );
+        assert_eq!(
+            parse_reports("0101\n011\n0000"),
+            Err(ReportParseError::WrongWidth {
+                line_number: 2,
+                expected: 4,
+                found: 3
+            })
+        );
+        assert_eq!(
+            parse_reports("0101\n0121"),
+            Err(ReportParseError::InvalidCharacter {
+                line_number: 2,
+                column: 2,
+                found: '2'
+            })
         );
     }
 
     #[test]
-    fn test_bits_to_u32() {
-        assert_eq!(
-            bits_to_u32(&[
-                false, false, false, false, false, false, false, false, false, false, false, true
-            ]),
-            1
-        );
+    fn test_sumarise() {
         assert_eq!(
-            bits_to_u32(&[
-                false, false, false, false, false, false, false, false, false, false, true, true
-            ]),
-            3
+            summarise(&Reports {
+                width: 12,
+                values: vec![0b000000000100, 0b000000000100]
+            }),
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0]
         );
     }
 
     #[test]
     fn test_calculate_e_g() {
         assert_eq!(
-            calculate_e_g(vec![
-                Report([
-                    false, false, false, false, false, false, false, false, false, false, false,
-                    true
-                ]),
-                Report([
-                    false, false, false, false, false, false, false, false, false, false, true,
-                    true
-                ])
-            ]),
+            calculate_e_g(&Reports {
+                width: 12,
+                values: vec![0b000000000001, 0b000000000011]
+            }),
             (3, 4092)
         );
     }
+
+    #[test]
+    fn test_full_width() {
+        let reports = parse_reports(&format!("{}\n{}", "1".repeat(64), "0".repeat(64))).unwrap();
+
+        assert_eq!(calculate_e_g(&reports), (u64::MAX, 0));
+    }
+
+    #[test]
+    fn test_trie_matches_filtering() {
+        // Every collection of 3-bit reports holding each value at most twice, which covers ties,
+        // duplicates and filters that empty the set.
+        let fixtures = (0..3u32.pow(8)).map(|multiplicities| Reports {
+            width: 3,
+            values: (0..8)
+                .flat_map(|value| {
+                    let copies = (multiplicities / 3u32.pow(value)) % 3;
+                    (0..copies).map(move |_| value as u64)
+                })
+                .collect(),
+        });
+
+        for reports in fixtures.chain(std::iter::once(get_test_reports())) {
+            for bit_order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+                for keep in [Commonness::Most, Commonness::Least] {
+                    for tie_break in [false, true] {
+                        let criterion = BitCriterion {
+                            keep,
+                            tie_break,
+                            bit_order,
+                        };
+
+                        assert_eq!(
+                            rate(&reports, criterion),
+                            rate_by_filtering(&reports, criterion),
+                            "{:?} with {:?}",
+                            reports,
+                            criterion
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
@@ -1,19 +1,42 @@
 //! Let's have a nice game of bingo
 
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+struct BingoRules {
+    diagonals_win: bool,
+    /// The centre square starts marked, and whatever number is written there never counts.
+    free_centre: bool,
+}
+
+/// A square board, stored row by row.
 #[derive(Debug, PartialEq, Clone)]
-struct Bingo {
-    board: [(u32, bool); 25],
+struct BingoBoard {
+    size: usize,
+    board: Vec<(u32, bool)>,
+    rules: BingoRules,
 }
 
-impl Bingo {
-    fn new(numbers: [u32; 25]) -> Self {
-        Bingo {
-            board: numbers.map(|n| (n, false)),
+impl BingoBoard {
+    fn new(size: usize, numbers: &[u32], rules: BingoRules) -> Self {
+        assert_eq!(numbers.len(), size * size, "Board is not {0}x{0}", size);
+        assert!(
+            !rules.free_centre || size % 2 == 1,
+            "A {0}x{0} board has no centre square",
+            size
+        );
+
+        let mut board: Vec<_> = numbers.iter().map(|&n| (n, false)).collect();
+        if rules.free_centre {
+            board[size * size / 2].1 = true;
         }
+
+        BingoBoard { size, board, rules }
     }
 
+    #[allow(dead_code)]
     fn mark(&mut self, called_number: u32) -> Option<u32> {
-        for mut entry in &mut self.board {
+        for entry in &mut self.board {
             if entry.0 == called_number {
                 entry.1 = true;
             }
@@ -26,32 +49,37 @@ impl Bingo {
         }
     }
 
+    fn mark_cell(&mut self, cell: usize) {
+        self.board[cell].1 = true;
+    }
+
+    /// Only the lines through a newly marked cell can have just been completed, so only those need
+    /// checking.
+    fn completes_line(&self, cell: usize) -> bool {
+        let n = self.size;
+        let (row, column) = (cell / n, cell % n);
+
+        (0..n).all(|c| self.is_marked(row, c))
+            || (0..n).all(|r| self.is_marked(r, column))
+            || (self.rules.diagonals_win
+                && ((row == column && (0..n).all(|i| self.is_marked(i, i)))
+                    || (row + column == n - 1 && (0..n).all(|i| self.is_marked(i, n - 1 - i)))))
+    }
+
+    fn is_marked(&self, row: usize, column: usize) -> bool {
+        self.board[row * self.size + column].1
+    }
+
     fn has_won(&self) -> bool {
-        for i in 0..5 {
-            if self
-                .board
-                .iter()
-                .skip(5 * i)
-                .take(5)
-                .all(|&(_, marked)| marked)
-            {
-                return true;
-            }
-        }
+        let n = self.size;
 
-        for i in 0..5 {
-            if self
-                .board
-                .iter()
-                .skip(i)
-                .step_by(5)
-                .all(|&(_, marked)| marked)
-            {
-                return true;
-            }
-        }
+        let row_complete = (0..n).any(|row| (0..n).all(|column| self.is_marked(row, column)));
+        let column_complete = (0..n).any(|column| (0..n).all(|row| self.is_marked(row, column)));
+        let diagonal_complete = self.rules.diagonals_win
+            && ((0..n).all(|i| self.is_marked(i, i))
+                || (0..n).all(|i| self.is_marked(i, n - 1 - i)));
 
-        false
+        row_complete || column_complete || diagonal_complete
     }
 
     fn get_sum_of_unmarked_entries(&self) -> u32 {
@@ -62,83 +90,210 @@ impl Bingo {
     }
 }
 
-fn play_boards_to_first_victory(called_numbers: &[u32], bingo_boards: &[Bingo]) {
-    let mut bingo_boards = bingo_boards.to_vec();
-
-    for called_number in called_numbers {
-        for bingo_board in &mut bingo_boards {
-            match bingo_board.mark(*called_number) {
-                Some(sum_of_unmarked_entries) => {
-                    println!(
-                        "number: {}, sum: {}, product: {}",
-                        called_number,
-                        sum_of_unmarked_entries,
-                        called_number * sum_of_unmarked_entries
-                    );
-                    return;
-                }
-                None => {}
+/// Boards are counted from 1, in the order they appear in the input.
+#[derive(Debug, PartialEq)]
+enum BingoParseError {
+    MissingCalledNumbers,
+    InvalidNumber(String),
+    IncompleteBoard {
+        board: usize,
+        expected_cells: usize,
+        found_cells: usize,
+    },
+    NotSquare {
+        board: usize,
+    },
+    NoCentre {
+        board: usize,
+        size: usize,
+    },
+}
+
+fn parse_numbers<'a>(numbers: impl Iterator<Item = &'a str>) -> Result<Vec<u32>, BingoParseError> {
+    numbers
+        .map(|n| {
+            n.parse()
+                .map_err(|_| BingoParseError::InvalidNumber(n.to_string()))
+        })
+        .collect()
+}
+
+/// The board's size is taken from the width of its first row.
+fn parse_board(
+    board: usize,
+    rows: &[&str],
+    rules: BingoRules,
+) -> Result<BingoBoard, BingoParseError> {
+    let rows = rows
+        .iter()
+        .map(|row| parse_numbers(row.split_whitespace()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let size = rows[0].len();
+    let found_cells = rows.iter().map(|row| row.len()).sum();
+
+    if rows.len() < size || rows.iter().any(|row| row.len() < size) {
+        return Err(BingoParseError::IncompleteBoard {
+            board,
+            expected_cells: size * size,
+            found_cells,
+        });
+    }
+    if rows.len() > size || found_cells > size * size {
+        return Err(BingoParseError::NotSquare { board });
+    }
+    if rules.free_centre && size % 2 == 0 {
+        return Err(BingoParseError::NoCentre { board, size });
+    }
+
+    Ok(BingoBoard::new(size, &rows.concat(), rules))
+}
+
+/// The called numbers come first, followed by the boards, each separated by a blank line.
+fn parse_game(
+    input: &str,
+    rules: BingoRules,
+) -> Result<(Vec<u32>, Vec<BingoBoard>), BingoParseError> {
+    let mut lines = input.lines().map(str::trim);
+
+    let called_numbers = match lines.next() {
+        Some(line) if !line.is_empty() => parse_numbers(line.split(','))?,
+        _ => return Err(BingoParseError::MissingCalledNumbers),
+    };
+
+    let mut blocks: Vec<Vec<&str>> = Vec::new();
+    let mut block = Vec::new();
+    for line in lines.chain(std::iter::once("")) {
+        if line.is_empty() {
+            if !block.is_empty() {
+                blocks.push(std::mem::take(&mut block));
             }
+        } else {
+            block.push(line);
         }
     }
+
+    let bingo_boards = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, rows)| parse_board(i + 1, rows, rules))
+        .collect::<Result<_, _>>()?;
+
+    Ok((called_numbers, bingo_boards))
+}
+
+/// Boards and draws are both indexed from 0, in input order.
+#[derive(Debug, PartialEq)]
+struct Winner {
+    board: usize,
+    draw: usize,
+    number: u32,
+    score: u32,
 }
 
-fn play_board_to_victory(called_numbers: &[u32], bingo_board: &mut Bingo) -> (usize, u32, u32) {
-    for (index, called_number) in called_numbers.iter().enumerate() {
-        match bingo_board.mark(*called_number) {
-            Some(sum_unmarked) => return (index, *called_number, sum_unmarked),
-            None => {}
+#[derive(Debug, PartialEq)]
+struct GameResult {
+    /// In the order the boards won. Boards completing on the same draw are ordered by board index.
+    winners: Vec<Winner>,
+    never_won: Vec<usize>,
+}
+
+struct BingoGame {
+    boards: Vec<BingoBoard>,
+    /// Every (board, cell) holding each number, so a draw only visits the cells it marks.
+    cells_by_number: HashMap<u32, Vec<(usize, usize)>>,
+}
+
+impl BingoGame {
+    fn new(boards: Vec<BingoBoard>) -> Self {
+        let mut cells_by_number: HashMap<u32, Vec<(usize, usize)>> = HashMap::new();
+
+        for (board_index, board) in boards.iter().enumerate() {
+            for (cell, &(number, marked)) in board.board.iter().enumerate() {
+                if !marked {
+                    cells_by_number
+                        .entry(number)
+                        .or_default()
+                        .push((board_index, cell));
+                }
+            }
+        }
+
+        BingoGame {
+            boards,
+            cells_by_number,
         }
     }
 
-    panic!("Game did not finish")
-}
+    fn play(mut self, called_numbers: &[u32]) -> GameResult {
+        let mut has_won = vec![false; self.boards.len()];
+        let mut winners = Vec::new();
+
+        for (draw, &number) in called_numbers.iter().enumerate() {
+            if winners.len() == self.boards.len() {
+                break;
+            }
 
-fn play_boards_to_last_victory(called_numbers: &[u32], bingo_boards: &[Bingo]) {
-    let mut bingo_boards = bingo_boards.to_vec();
+            let cells = match self.cells_by_number.get(&number) {
+                Some(cells) => cells,
+                None => continue,
+            };
 
-    let (victory_time, last_called_number, sum_of_unmarked_entries) = bingo_boards
-        .iter_mut()
-        .map(|board| play_board_to_victory(called_numbers, board))
-        .max_by_key(|(victory_time, _last_called_number, _sum_unmarked)| victory_time.clone())
-        .unwrap();
+            // A number can appear more than once on a board, so every copy is marked before any
+            // board is scored.
+            for &(board_index, cell) in cells {
+                if !has_won[board_index] {
+                    self.boards[board_index].mark_cell(cell);
+                }
+            }
 
-    println!(
-        "Won at {}, number: {}, sum: {}, product: {}",
-        victory_time,
-        last_called_number,
-        sum_of_unmarked_entries,
-        last_called_number * sum_of_unmarked_entries
-    );
+            for &(board_index, cell) in cells {
+                let board = &self.boards[board_index];
+
+                if !has_won[board_index] && board.completes_line(cell) {
+                    has_won[board_index] = true;
+                    winners.push(Winner {
+                        board: board_index,
+                        draw,
+                        number,
+                        score: number * board.get_sum_of_unmarked_entries(),
+                    });
+                }
+            }
+        }
+
+        GameResult {
+            winners,
+            never_won: (0..self.boards.len())
+                .filter(|&board_index| !has_won[board_index])
+                .collect(),
+        }
+    }
 }
 
 fn main() {
     let input = std::fs::read_to_string("data/day04.txt").unwrap();
-    let mut input_entries = input.split_whitespace();
-
-    let called_numbers: Vec<u32> = input_entries
-        .next()
-        .unwrap()
-        .split(',')
-        .map(|c| c.parse().unwrap())
-        .collect();
-
-    let mut bingo_numbers: Vec<u32> = input_entries.map(|c| c.parse().unwrap()).collect();
-    let bingo_boards: Vec<Bingo> = bingo_numbers
-        .chunks_exact_mut(25)
-        .map(|n| Bingo::new(n.to_owned().try_into().unwrap()))
-        .collect();
-
-    play_boards_to_first_victory(&called_numbers, &bingo_boards);
-    play_boards_to_last_victory(&called_numbers, &bingo_boards);
+    let (called_numbers, bingo_boards) = parse_game(&input, BingoRules::default()).unwrap();
+
+    let result = BingoGame::new(bingo_boards).play(&called_numbers);
+
+    if let Some(first) = result.winners.first() {
+        println!("First winner: {:?}", first);
+    }
+    if let Some(last) = result.winners.last() {
+        println!("Last winner: {:?}", last);
+    }
+    if !result.never_won.is_empty() {
+        println!("Boards that never won: {:?}", result.never_won);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn get_test_board() -> Bingo {
-        Bingo::new((0..25).collect::<Vec<u32>>().try_into().unwrap())
+    fn get_test_board() -> BingoBoard {
+        BingoBoard::new(5, &(0..25).collect::<Vec<u32>>(), BingoRules::default())
     }
 
     #[test]
@@ -181,51 +336,276 @@ mod tests {
         assert!(board.mark(20).is_some());
     }
 
+    fn play_board_to_victory(called_numbers: &[u32], board: BingoBoard) -> Winner {
+        BingoGame::new(vec![board])
+            .play(called_numbers)
+            .winners
+            .remove(0)
+    }
+
     #[test]
     fn test_play_board_to_victory() {
-        let mut board = get_test_board();
-
         assert_eq!(
-            play_board_to_victory(&[0, 1, 2, 3, 4, 5], &mut board),
-            (4, 4, (5..25).sum())
+            play_board_to_victory(&[0, 1, 2, 3, 4, 5], get_test_board()),
+            Winner {
+                board: 0,
+                draw: 4,
+                number: 4,
+                score: 4 * (5..25).sum::<u32>()
+            }
         );
     }
 
     #[test]
     fn test_expected_victory_example3() {
-        let mut board = Bingo::new([
-            14, 21, 17, 24, 4, 10, 16, 15, 9, 19, 18, 8, 23, 26, 20, 22, 11, 13, 6, 5, 2, 0, 12, 3,
-            7,
-        ]);
+        let board = BingoBoard::new(
+            5,
+            &[
+                14, 21, 17, 24, 4, 10, 16, 15, 9, 19, 18, 8, 23, 26, 20, 22, 11, 13, 6, 5, 2, 0,
+                12, 3, 7,
+            ],
+            BingoRules::default(),
+        );
 
-        let (_, called_number, sum_unmarked) = play_board_to_victory(
+        let winner = play_board_to_victory(
             &[
                 7, 4, 9, 5, 11, 17, 23, 2, 0, 14, 21, 24, 10, 16, 13, 6, 15, 25, 12, 22, 18, 20, 8,
                 19, 3, 26, 1,
             ],
-            &mut board,
+            board,
         );
 
-        assert_eq!(called_number, 24);
-        assert_eq!(sum_unmarked, 188);
+        assert_eq!(winner.number, 24);
+        assert_eq!(winner.score, 24 * 188);
     }
 
     #[test]
     fn test_expected_victory_example2() {
-        let mut board = Bingo::new([
-            3, 15, 0, 2, 22, 9, 18, 13, 17, 5, 19, 8, 7, 25, 23, 20, 11, 10, 24, 4, 14, 21, 16, 12,
-            6,
-        ]);
+        let board = BingoBoard::new(
+            5,
+            &[
+                3, 15, 0, 2, 22, 9, 18, 13, 17, 5, 19, 8, 7, 25, 23, 20, 11, 10, 24, 4, 14, 21, 16,
+                12, 6,
+            ],
+            BingoRules::default(),
+        );
 
-        let (_, called_number, sum_unmarked) = play_board_to_victory(
+        let winner = play_board_to_victory(
             &[
                 7, 4, 9, 5, 11, 17, 23, 2, 0, 14, 21, 24, 10, 16, 13, 6, 15, 25, 12, 22, 18, 20, 8,
                 19, 3, 26, 1,
             ],
-            &mut board,
+            board,
+        );
+
+        assert_eq!(winner.number, 13);
+        assert_eq!(winner.score, 13 * 148);
+    }
+
+    #[test]
+    fn test_diagonals() {
+        let mut board = get_test_board();
+        for n in [0, 6, 12, 18] {
+            board.mark(n);
+        }
+        assert_eq!(board.mark(24), None);
+
+        let mut board = BingoBoard::new(
+            5,
+            &(0..25).collect::<Vec<u32>>(),
+            BingoRules {
+                diagonals_win: true,
+                free_centre: false,
+            },
+        );
+        for n in [4, 8, 12, 16] {
+            assert_eq!(board.mark(n), None);
+        }
+        assert!(board.mark(20).is_some());
+    }
+
+    #[test]
+    fn test_free_centre() {
+        let mut board = BingoBoard::new(
+            3,
+            &(1..10).collect::<Vec<u32>>(),
+            BingoRules {
+                diagonals_win: false,
+                free_centre: true,
+            },
+        );
+
+        assert_eq!(board.get_sum_of_unmarked_entries(), 45 - 5);
+        assert_eq!(board.mark(4), None);
+        assert_eq!(board.mark(6), Some(45 - 5 - 4 - 6));
+    }
+
+    #[test]
+    fn test_parse_game() {
+        let (called_numbers, boards) = parse_game(
+            "3,1,4\n\n1 2\n3 4\n\n\n 5  6\n 7  8\n",
+            BingoRules::default(),
+        )
+        .unwrap();
+
+        assert_eq!(called_numbers, [3, 1, 4]);
+        assert_eq!(
+            boards,
+            [
+                BingoBoard::new(2, &[1, 2, 3, 4], BingoRules::default()),
+                BingoBoard::new(2, &[5, 6, 7, 8], BingoRules::default())
+            ]
         );
+    }
 
-        assert_eq!(called_number, 13);
-        assert_eq!(sum_unmarked, 148);
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_game("", BingoRules::default()),
+            Err(BingoParseError::MissingCalledNumbers)
+        );
+        assert_eq!(
+            parse_game("1,x\n\n1", BingoRules::default()),
+            Err(BingoParseError::InvalidNumber("x".to_string()))
+        );
+        assert_eq!(
+            parse_game("1\n\n1 2\n3 4\n\n1 2 3\n4 5 6", BingoRules::default()),
+            Err(BingoParseError::IncompleteBoard {
+                board: 2,
+                expected_cells: 9,
+                found_cells: 6
+            })
+        );
+        assert_eq!(
+            parse_game("1\n\n1 2\n3", BingoRules::default()),
+            Err(BingoParseError::IncompleteBoard {
+                board: 1,
+                expected_cells: 4,
+                found_cells: 3
+            })
+        );
+        assert_eq!(
+            parse_game("1\n\n1 2\n3 4 5", BingoRules::default()),
+            Err(BingoParseError::NotSquare { board: 1 })
+        );
+        assert_eq!(
+            parse_game(
+                "1\n\n1 2\n3 4",
+                BingoRules {
+                    diagonals_win: false,
+                    free_centre: true
+                }
+            ),
+            Err(BingoParseError::NoCentre { board: 1, size: 2 })
+        );
+    }
+
+    #[test]
+    fn test_example_game() {
+        let (called_numbers, boards) = parse_game(
+            "7,4,9,5,11,17,23,2,0,14,21,24,10,16,13,6,15,25,12,22,18,20,8,19,3,26,1
+
+22 13 17 11  0
+ 8  2 23  4 24
+21  9 14 16  7
+ 6 10  3 18  5
+ 1 12 20 15 19
+
+ 3 15  0  2 22
+ 9 18 13 17  5
+19  8  7 25 23
+20 11 10 24  4
+14 21 16 12  6
+
+14 21 17 24  4
+10 16 15  9 19
+18  8 23 26 20
+22 11 13  6  5
+ 2  0 12  3  7",
+            BingoRules::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            BingoGame::new(boards).play(&called_numbers),
+            GameResult {
+                winners: vec![
+                    Winner {
+                        board: 2,
+                        draw: 11,
+                        number: 24,
+                        score: 4512
+                    },
+                    Winner {
+                        board: 0,
+                        draw: 13,
+                        number: 16,
+                        score: 2192
+                    },
+                    Winner {
+                        board: 1,
+                        draw: 14,
+                        number: 13,
+                        score: 1924
+                    }
+                ],
+                never_won: vec![]
+            }
+        );
+    }
+
+    #[test]
+    fn test_repeated_numbers() {
+        let mut board = BingoBoard::new(2, &[1, 2, 3, 1], BingoRules::default());
+        board.mark(2);
+        assert_eq!(board.mark(1), Some(3));
+
+        let board = BingoBoard::new(2, &[1, 2, 3, 1], BingoRules::default());
+        assert_eq!(
+            play_board_to_victory(&[2, 1], board),
+            Winner {
+                board: 0,
+                draw: 1,
+                number: 1,
+                score: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_boards_that_never_win() {
+        let boards = vec![
+            BingoBoard::new(2, &[1, 2, 3, 4], BingoRules::default()),
+            BingoBoard::new(2, &[5, 6, 7, 8], BingoRules::default()),
+            BingoBoard::new(2, &[1, 9, 3, 10], BingoRules::default()),
+            BingoBoard::new(2, &[3, 11, 3, 12], BingoRules::default()),
+        ];
+
+        assert_eq!(
+            BingoGame::new(boards).play(&[1, 5, 3]),
+            GameResult {
+                winners: vec![
+                    Winner {
+                        board: 0,
+                        draw: 2,
+                        number: 3,
+                        score: 3 * 6
+                    },
+                    Winner {
+                        board: 2,
+                        draw: 2,
+                        number: 3,
+                        score: 3 * 19
+                    },
+                    Winner {
+                        board: 3,
+                        draw: 2,
+                        number: 3,
+                        score: 3 * 23
+                    }
+                ],
+                never_won: vec![1]
+            }
+        );
     }
 }
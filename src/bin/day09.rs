@@ -7,6 +7,41 @@ struct HeightMap {
     heights: Vec<Vec<u8>>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Connectivity {
+    Four,
+    #[allow(dead_code)]
+    Eight,
+}
+
+/// Describes what separates one basin from another, and what counts as the bottom of a basin.
+struct BasinRules {
+    is_wall: Box<dyn Fn(u8) -> bool>,
+    connectivity: Connectivity,
+    plateaus_are_low_points: bool,
+}
+
+impl BasinRules {
+    fn new(
+        is_wall: impl Fn(u8) -> bool + 'static,
+        connectivity: Connectivity,
+        plateaus_are_low_points: bool,
+    ) -> Self {
+        BasinRules {
+            is_wall: Box::new(is_wall),
+            connectivity,
+            plateaus_are_low_points,
+        }
+    }
+}
+
+impl Default for BasinRules {
+    /// The rules from the puzzle: basins are bounded by 9s, and only strict minima are low points.
+    fn default() -> Self {
+        BasinRules::new(|height| height == 9, Connectivity::Four, false)
+    }
+}
+
 impl HeightMap {
     fn max_x(&self) -> usize {
         self.heights.len()
@@ -16,42 +51,145 @@ impl HeightMap {
         self.heights[0].len()
     }
 
-    fn get_adjacent_points(&self, x: usize, y: usize) -> HashSet<Point> {
+    fn get_adjacent_points(
+        &self,
+        x: usize,
+        y: usize,
+        connectivity: Connectivity,
+    ) -> HashSet<Point> {
         let mut adjacents = HashSet::new();
 
-        if x >= 1 {
-            adjacents.insert((x - 1, y, self.heights[x - 1][y]));
-        }
-        if x < self.max_x() - 1 {
-            adjacents.insert((x + 1, y, self.heights[x + 1][y]));
-        }
+        let min_x = x.saturating_sub(1);
+        let max_x = (x + 1).min(self.max_x() - 1);
 
-        if y >= 1 {
-            adjacents.insert((x, y - 1, self.heights[x][y - 1]));
-        }
-        if y < self.max_y() - 1 {
-            adjacents.insert((x, y + 1, self.heights[x][y + 1]));
+        let min_y = y.saturating_sub(1);
+        let max_y = (y + 1).min(self.max_y() - 1);
+
+        for adjacent_x in min_x..=max_x {
+            for adjacent_y in min_y..=max_y {
+                let is_diagonal = adjacent_x != x && adjacent_y != y;
+
+                if (adjacent_x, adjacent_y) == (x, y)
+                    || (is_diagonal && connectivity == Connectivity::Four)
+                {
+                    continue;
+                }
+
+                adjacents.insert((adjacent_x, adjacent_y, self.heights[adjacent_x][adjacent_y]));
+            }
         }
 
         adjacents
     }
 
+    /// All the points connected to the given one by a path that doesn't leave `is_included`.
+    fn flood_fill(
+        &self,
+        start: Point,
+        connectivity: Connectivity,
+        is_included: impl Fn(&Point) -> bool,
+    ) -> HashSet<Point> {
+        let mut region = HashSet::new();
+        region.insert(start);
+
+        let mut to_visit = vec![start];
+
+        while let Some((x, y, _)) = to_visit.pop() {
+            for adjacent_point in self.get_adjacent_points(x, y, connectivity) {
+                if is_included(&adjacent_point) && region.insert(adjacent_point) {
+                    to_visit.push(adjacent_point);
+                }
+            }
+        }
+
+        region
+    }
+
+    #[allow(dead_code)]
     fn is_low_point(&self, x: usize, y: usize) -> Option<Point> {
+        self.is_low_point_with(x, y, &BasinRules::default())
+    }
+
+    /// When plateaus count as low points, each plateau is reported once, at its first point in scan order.
+    fn is_low_point_with(&self, x: usize, y: usize, rules: &BasinRules) -> Option<Point> {
         let height = self.heights[x][y];
 
-        for (_, _, adjacent_height) in self.get_adjacent_points(x, y) {
-            if height >= adjacent_height {
-                return None;
+        if (rules.is_wall)(height) {
+            return None;
+        }
+
+        if !rules.plateaus_are_low_points {
+            for (_, _, adjacent_height) in self.get_adjacent_points(x, y, rules.connectivity) {
+                if height >= adjacent_height {
+                    return None;
+                }
             }
+
+            return Some((x, y, height));
+        }
+
+        let plateau = self.get_plateau(x, y, rules);
+
+        if plateau.iter().any(|&(px, py, _)| (px, py) < (x, y)) {
+            return None;
         }
 
-        Some((x, y, height))
+        if self.is_low_plateau(&plateau, rules) {
+            Some((x, y, height))
+        } else {
+            None
+        }
+    }
+
+    fn get_plateau(&self, x: usize, y: usize, rules: &BasinRules) -> HashSet<Point> {
+        let height = self.heights[x][y];
+
+        self.flood_fill((x, y, height), rules.connectivity, |p| p.2 == height)
+    }
+
+    fn is_low_plateau(&self, plateau: &HashSet<Point>, rules: &BasinRules) -> bool {
+        plateau.iter().all(|&(px, py, height)| {
+            self.get_adjacent_points(px, py, rules.connectivity)
+                .into_iter()
+                .all(|(_, _, adjacent_height)| adjacent_height >= height)
+        })
     }
 
     fn find_low_points(&self) -> Vec<Point> {
-        (0..self.max_x())
-            .flat_map(|x| (0..self.max_y()).filter_map(move |y| self.is_low_point(x, y)))
-            .collect()
+        self.find_low_points_with(&BasinRules::default())
+    }
+
+    /// Each plateau is flooded once, from its first point in scan order, so large flat areas cost no
+    /// more than any other region.
+    fn find_low_points_with(&self, rules: &BasinRules) -> Vec<Point> {
+        if !rules.plateaus_are_low_points {
+            return (0..self.max_x())
+                .flat_map(|x| {
+                    (0..self.max_y()).filter_map(move |y| self.is_low_point_with(x, y, rules))
+                })
+                .collect();
+        }
+
+        let mut visited = HashSet::new();
+        let mut low_points = Vec::new();
+
+        for x in 0..self.max_x() {
+            for y in 0..self.max_y() {
+                let height = self.heights[x][y];
+
+                if (rules.is_wall)(height) || visited.contains(&(x, y, height)) {
+                    continue;
+                }
+
+                let plateau = self.get_plateau(x, y, rules);
+                if self.is_low_plateau(&plateau, rules) {
+                    low_points.push((x, y, height));
+                }
+                visited.extend(plateau);
+            }
+        }
+
+        low_points
     }
 
     fn get_risk_level_sum(&self) -> u32 {
@@ -61,28 +199,32 @@ impl HeightMap {
             .sum()
     }
 
+    #[allow(dead_code)]
     fn get_basin_for_low_point(&self, low_point: Point) -> HashSet<Point> {
-        let mut basin = HashSet::new();
-        basin.insert(low_point);
-
-        for height in (low_point.2 + 1)..9 {
-            for basin_point in basin.clone() {
-                for adjacent_point in self.get_adjacent_points(basin_point.0, basin_point.1) {
-                    if adjacent_point.2 == height && !basin.contains(&adjacent_point) {
-                        basin.insert(adjacent_point);
-                    }
-                }
+        self.get_basin_for_low_point_with(low_point, &BasinRules::default())
+    }
+
+    fn get_basin_for_low_point_with(&self, low_point: Point, rules: &BasinRules) -> HashSet<Point> {
+        self.flood_fill(low_point, rules.connectivity, |p| !(rules.is_wall)(p.2))
+    }
+
+    /// Low points that share a basin with an earlier low point don't produce a second copy of it.
+    fn find_basins_with(&self, rules: &BasinRules) -> Vec<HashSet<Point>> {
+        let mut basins: Vec<HashSet<Point>> = Vec::new();
+
+        for low_point in self.find_low_points_with(rules) {
+            if !basins.iter().any(|basin| basin.contains(&low_point)) {
+                basins.push(self.get_basin_for_low_point_with(low_point, rules));
             }
         }
 
-        basin
+        basins
     }
 
     fn get_three_largest_basin_sizes(&self) -> usize {
         let mut basins: Vec<_> = self
-            .find_low_points()
+            .find_basins_with(&BasinRules::default())
             .into_iter()
-            .map(|l| self.get_basin_for_low_point(l))
             .map(|basin| basin.len())
             .collect();
 
@@ -189,4 +331,60 @@ mod tests {
 
         assert_eq!(map.get_basin_for_low_point((0, 9, 0)).len(), 9);
     }
+
+    #[test]
+    fn test_plateau_low_points() {
+        let map = HeightMap::from(
+            "22399
+22391
+33999"
+                .to_string(),
+        );
+
+        assert_eq!(map.find_low_points(), vec![(1, 4, 1)]);
+
+        let rules = BasinRules::new(|height| height == 9, Connectivity::Four, true);
+        assert_eq!(map.find_low_points_with(&rules), vec![(0, 0, 2), (1, 4, 1)]);
+    }
+
+    #[test]
+    fn test_diagonal_connectivity() {
+        let map = HeightMap::from("19\n92".to_string());
+
+        assert_eq!(map.find_basins_with(&BasinRules::default()).len(), 2);
+
+        let rules = BasinRules::new(|height| height == 9, Connectivity::Eight, false);
+        let basins = map.find_basins_with(&rules);
+        assert_eq!(basins.len(), 1);
+        assert_eq!(basins[0].len(), 2);
+    }
+
+    #[test]
+    fn test_custom_walls() {
+        let map = HeightMap::from(
+            "2199943210
+3987894921
+9856789892
+8767896789
+9899965678"
+                .to_string(),
+        );
+
+        let rules = BasinRules::new(|height| height >= 8, Connectivity::Four, false);
+        assert_eq!(map.get_basin_for_low_point_with((2, 2, 5), &rules).len(), 7);
+    }
+
+    #[test]
+    fn test_large_plateau() {
+        let row = "5".repeat(200);
+        let map = HeightMap::from(format!("{}\n{}\n{}1", row, row, &row[1..]));
+
+        let rules = BasinRules::new(|height| height == 9, Connectivity::Four, true);
+        assert_eq!(map.find_low_points_with(&rules), vec![(2, 199, 1)]);
+
+        let map = HeightMap::from(vec![&row[..100]; 100].join("\n"));
+        assert_eq!(map.find_low_points_with(&rules), vec![(0, 0, 5)]);
+        assert_eq!(map.is_low_point_with(0, 0, &rules), Some((0, 0, 5)));
+        assert_eq!(map.is_low_point_with(3, 7, &rules), None);
+    }
 }
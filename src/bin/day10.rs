@@ -1,109 +1,215 @@
 #[derive(Debug, PartialEq)]
-enum Bracket {
-    LeftRound,
-    RightRound,
-    LeftSquare,
-    RightSquare,
-    LeftBrace,
-    RightBrace,
-    LeftAngle,
-    RightAngle,
+struct BracketPair {
+    open: char,
+    close: char,
+    syntax_error_score: u64,
+    completion_score: u64,
 }
 
-impl Bracket {
-    fn is_left(&self) -> bool {
-        self == &Bracket::LeftRound
-            || self == &Bracket::LeftSquare
-            || self == &Bracket::LeftBrace
-            || self == &Bracket::LeftAngle
-    }
-
-    fn closed_by(&self, close_candidate: &Bracket) -> bool {
-        match (self, close_candidate) {
-            (Bracket::LeftRound, Bracket::RightRound)
-            | (Bracket::LeftSquare, Bracket::RightSquare)
-            | (Bracket::LeftBrace, Bracket::RightBrace)
-            | (Bracket::LeftAngle, Bracket::RightAngle) => true,
-            _ => false,
-        }
-    }
+/// A set of delimiter pairs, along with how to score lines that misuse them.
+///
+/// A pair may open and close with the same character, as quotes do. Directly inside such a pair, that
+/// character closes it. Further in, it's a wrong closer, since quotes can't nest inside themselves.
+#[derive(Debug, PartialEq)]
+struct BracketLanguage {
+    pairs: Vec<BracketPair>,
+    completion_base: u64,
 }
 
-impl From<char> for Bracket {
-    fn from(input: char) -> Bracket {
-        match input {
-            '(' => Bracket::LeftRound,
-            '[' => Bracket::LeftSquare,
-            '{' => Bracket::LeftBrace,
-            '<' => Bracket::LeftAngle,
+impl BracketLanguage {
+    /// Each pair is given as `(open, close, syntax error score, completion score)`.
+    fn new(pairs: &[(char, char, u64, u64)], completion_base: u64) -> Self {
+        let pairs: Vec<BracketPair> = pairs
+            .iter()
+            .map(
+                |&(open, close, syntax_error_score, completion_score)| BracketPair {
+                    open,
+                    close,
+                    syntax_error_score,
+                    completion_score,
+                },
+            )
+            .collect();
 
-            ')' => Bracket::RightRound,
-            ']' => Bracket::RightSquare,
-            '}' => Bracket::RightBrace,
-            '>' => Bracket::RightAngle,
+        for (i, pair) in pairs.iter().enumerate() {
+            for other in &pairs[i + 1..] {
+                assert!(
+                    pair.open != other.open
+                        && pair.close != other.close
+                        && pair.open != other.close
+                        && pair.close != other.open,
+                    "Ambiguous bracket pairs: {}{} and {}{}",
+                    pair.open,
+                    pair.close,
+                    other.open,
+                    other.close
+                );
+            }
+        }
 
-            i => panic!("Invalid char: {}", i),
+        BracketLanguage {
+            pairs,
+            completion_base,
         }
     }
-}
 
-fn parse_stack_fragment(line: &str) -> Result<Vec<Bracket>, Bracket> {
-    let mut stack = Vec::new();
+    fn day10() -> Self {
+        BracketLanguage::new(
+            &[
+                ('(', ')', 3, 1),
+                ('[', ']', 57, 2),
+                ('{', '}', 1197, 3),
+                ('<', '>', 25137, 4),
+            ],
+            5,
+        )
+    }
+
+    fn opened_by(&self, c: char) -> Option<&BracketPair> {
+        self.pairs.iter().find(|pair| pair.open == c)
+    }
+
+    fn closed_by(&self, c: char) -> Option<&BracketPair> {
+        self.pairs.iter().find(|pair| pair.close == c)
+    }
+
+    fn get_completion(&self, stack: &[char]) -> String {
+        stack
+            .iter()
+            .rev()
+            .filter_map(|&open| self.opened_by(open))
+            .map(|pair| pair.close)
+            .collect()
+    }
+
+    /// Returns the brackets left open at the end of the line, or the first point at which it's corrupted.
+    fn parse_stack_fragment(&self, line: &str) -> Result<Vec<char>, Diagnostic> {
+        let mut stack: Vec<char> = Vec::new();
+
+        for (column, c) in line.chars().enumerate() {
+            let expected = stack
+                .last()
+                .and_then(|&open| self.opened_by(open))
+                .map(|pair| pair.close);
+
+            if expected == Some(c) {
+                stack.pop();
+                continue;
+            }
 
-    for c in line.chars() {
-        let bracket = Bracket::from(c);
+            let is_open_quote = self
+                .opened_by(c)
+                .is_some_and(|pair| pair.close == c && stack.contains(&c));
 
-        if bracket.is_left() {
-            stack.push(bracket);
-        } else {
-            if !stack.pop().unwrap().closed_by(&bracket) {
-                return Err(bracket);
+            if self.opened_by(c).is_some() && !is_open_quote {
+                stack.push(c);
+                continue;
             }
+
+            // Only a wrong closer can be swapped for the right one. Anything else is just in the way.
+            let (kind, fix) = match expected {
+                _ if self.closed_by(c).is_none() => (ErrorKind::InvalidCharacter, Fix::Remove),
+                None => (ErrorKind::UnmatchedCloser, Fix::Remove),
+                Some(expected) => (ErrorKind::UnexpectedCloser, Fix::Replace(expected)),
+            };
+
+            return Err(Diagnostic {
+                kind,
+                column,
+                found: Some(c),
+                expected,
+                fix,
+            });
         }
+
+        Ok(stack)
     }
 
-    Ok(stack)
-}
+    fn validate(&self, line: &str) -> Result<(), Diagnostic> {
+        let stack = self.parse_stack_fragment(line)?;
+
+        if stack.is_empty() {
+            return Ok(());
+        }
+
+        let completion = self.get_completion(&stack);
+
+        Err(Diagnostic {
+            kind: ErrorKind::Incomplete,
+            column: line.chars().count(),
+            found: None,
+            expected: completion.chars().next(),
+            fix: Fix::Append(completion),
+        })
+    }
 
-fn get_syntax_error_cost(line: &str) -> u32 {
-    match parse_stack_fragment(line) {
-        Err(Bracket::RightRound) => 3,
-        Err(Bracket::RightSquare) => 57,
-        Err(Bracket::RightBrace) => 1197,
-        Err(Bracket::RightAngle) => 25137,
-        _ => 0,
+    fn get_syntax_error_cost(&self, line: &str) -> u64 {
+        match self.validate(line) {
+            Err(Diagnostic {
+                kind: ErrorKind::UnexpectedCloser | ErrorKind::UnmatchedCloser,
+                found: Some(found),
+                ..
+            }) => self
+                .closed_by(found)
+                .map(|pair| pair.syntax_error_score)
+                .unwrap_or(0),
+            _ => 0,
+        }
     }
+
+    fn get_completion_cost(&self, line: &str) -> Result<u64, Diagnostic> {
+        Ok(self
+            .parse_stack_fragment(line)?
+            .into_iter()
+            .rev()
+            .filter_map(|open| self.opened_by(open))
+            .fold(0, |acc, pair| {
+                acc * self.completion_base + pair.completion_score
+            }))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ErrorKind {
+    /// A closing bracket that doesn't match the innermost open chunk.
+    UnexpectedCloser,
+    /// A closing bracket when there are no open chunks at all.
+    UnmatchedCloser,
+    InvalidCharacter,
+    Incomplete,
+}
+
+#[derive(Debug, PartialEq)]
+enum Fix {
+    Append(String),
+    Replace(char),
+    Remove,
 }
 
-fn get_completion_cost(line: &str) -> Result<u64, Bracket> {
-    Ok(parse_stack_fragment(line)?
-        .into_iter()
-        .rev()
-        .fold(0, |acc, bracket| {
-            acc * 5
-                + match bracket {
-                    Bracket::LeftRound => 1,
-                    Bracket::LeftSquare => 2,
-                    Bracket::LeftBrace => 3,
-                    Bracket::LeftAngle => 4,
-                    _ => panic!("Unexpected bracket in stack remnant"),
-                }
-        }))
+#[derive(Debug, PartialEq)]
+struct Diagnostic {
+    kind: ErrorKind,
+    /// Zero-based character offset into the line. For incomplete lines, this is the end of the line.
+    column: usize,
+    found: Option<char>,
+    expected: Option<char>,
+    fix: Fix,
 }
 
 fn main() {
     let data = std::fs::read_to_string("data/day10.txt").unwrap();
+    let language = BracketLanguage::day10();
 
     println!(
         "Syntax error cost: {}",
         data.split('\n')
-            .map(|line| get_syntax_error_cost(line))
-            .sum::<u32>()
+            .map(|line| language.get_syntax_error_cost(line))
+            .sum::<u64>()
     );
 
     let mut completions: Vec<u64> = data
         .split('\n')
-        .map(|line| get_completion_cost(line))
+        .map(|line| language.get_completion_cost(line))
         .filter_map(Result::ok)
         .collect();
     completions.sort();
@@ -118,21 +224,185 @@ mod tests {
     #[test]
     fn test_parse_line() {
         assert_eq!(
-            parse_stack_fragment("{([(<{}[<>[]}>{[]{[(<()>"),
-            Err(Bracket::RightBrace)
+            BracketLanguage::day10().parse_stack_fragment("{([(<{}[<>[]}>{[]{[(<()>"),
+            Err(Diagnostic {
+                kind: ErrorKind::UnexpectedCloser,
+                column: 12,
+                found: Some('}'),
+                expected: Some(']'),
+                fix: Fix::Replace(']'),
+            })
         );
 
-        assert_eq!(parse_stack_fragment("{}()<<>>"), Ok(vec![]));
         assert_eq!(
-            parse_stack_fragment("{}()<<"),
-            Ok(vec![Bracket::LeftAngle, Bracket::LeftAngle])
+            BracketLanguage::day10().parse_stack_fragment("{}()<<>>"),
+            Ok(vec![])
+        );
+        assert_eq!(
+            BracketLanguage::day10().parse_stack_fragment("{}()<<"),
+            Ok(vec!['<', '<'])
         );
     }
 
     #[test]
     fn test_completion_cost() {
-        assert_eq!(get_completion_cost("[({(<(())[]>[[{[]{<()<>>"), Ok(288957));
+        assert_eq!(
+            BracketLanguage::day10().get_completion_cost("[({(<(())[]>[[{[]{<()<>>"),
+            Ok(288957)
+        );
+
+        assert_eq!(
+            BracketLanguage::day10().get_completion_cost("<{([{{}}[<[[[<>{}]]]>[]]"),
+            Ok(294)
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(BracketLanguage::day10().validate("{}()<<>>"), Ok(()));
+
+        assert_eq!(
+            BracketLanguage::day10().validate("[({(<(())[]>[[{[]{<()<>>"),
+            Err(Diagnostic {
+                kind: ErrorKind::Incomplete,
+                column: 24,
+                found: None,
+                expected: Some('}'),
+                fix: Fix::Append("}}]])})]".to_string()),
+            })
+        );
+
+        assert_eq!(
+            BracketLanguage::day10().validate("()]"),
+            Err(Diagnostic {
+                kind: ErrorKind::UnmatchedCloser,
+                column: 2,
+                found: Some(']'),
+                expected: None,
+                fix: Fix::Remove,
+            })
+        );
+
+        assert_eq!(
+            BracketLanguage::day10().validate("(a)"),
+            Err(Diagnostic {
+                kind: ErrorKind::InvalidCharacter,
+                column: 1,
+                found: Some('a'),
+                expected: Some(')'),
+                fix: Fix::Remove,
+            })
+        );
+    }
+
+    #[test]
+    fn test_syntax_error_cost() {
+        assert_eq!(
+            BracketLanguage::day10().get_syntax_error_cost("{([(<{}[<>[]}>{[]{[(<()>"),
+            1197
+        );
+        assert_eq!(BracketLanguage::day10().get_syntax_error_cost(">"), 25137);
+        assert_eq!(BracketLanguage::day10().get_syntax_error_cost("(x"), 0);
+        assert_eq!(BracketLanguage::day10().get_syntax_error_cost("(("), 0);
+    }
+
+    #[test]
+    fn test_custom_language() {
+        let language =
+            BracketLanguage::new(&[('"', '"', 1, 1), ('(', ')', 2, 2), ('b', 'e', 3, 3)], 10);
+
+        assert_eq!(language.validate("(\"b()e\")"), Ok(()));
+        assert_eq!(language.get_completion_cost("(\"b"), Ok(312));
+        assert_eq!(language.get_syntax_error_cost("(\"b)"), 2);
+        assert_eq!(
+            language.validate("(<)"),
+            Err(Diagnostic {
+                kind: ErrorKind::InvalidCharacter,
+                column: 1,
+                found: Some('<'),
+                expected: Some(')'),
+                fix: Fix::Remove,
+            })
+        );
+        assert_eq!(
+            language.validate("\"(\""),
+            Err(Diagnostic {
+                kind: ErrorKind::UnexpectedCloser,
+                column: 2,
+                found: Some('"'),
+                expected: Some(')'),
+                fix: Fix::Replace(')'),
+            })
+        );
+        assert_eq!(language.get_syntax_error_cost("\"(\""), 1);
+        assert_eq!(
+            language.validate("(\"(\")\")"),
+            Err(Diagnostic {
+                kind: ErrorKind::UnexpectedCloser,
+                column: 3,
+                found: Some('"'),
+                expected: Some(')'),
+                fix: Fix::Replace(')'),
+            })
+        );
+        assert_eq!(language.validate("(\"()\")"), Ok(()));
+    }
+
+    fn apply_fix(line: &str, diagnostic: &Diagnostic) -> String {
+        let mut chars: Vec<char> = line.chars().collect();
+
+        match &diagnostic.fix {
+            Fix::Append(completion) => chars.extend(completion.chars()),
+            Fix::Replace(c) => chars[diagnostic.column] = *c,
+            Fix::Remove => {
+                chars.remove(diagnostic.column);
+            }
+        }
+
+        chars.into_iter().collect()
+    }
+
+    #[test]
+    fn test_fixes_repair_line() {
+        let language = BracketLanguage::day10();
+
+        for line in [
+            "(a)",
+            "{()]",
+            "()]",
+            "[<>({}){}[([])<>]]>",
+            "[({(<(())[]>[[{[]{<()<>>",
+        ] {
+            let diagnostic = language.validate(line).unwrap_err();
+            let fixed = apply_fix(line, &diagnostic);
+
+            match language.validate(&fixed) {
+                Ok(()) => {}
+                Err(Diagnostic {
+                    kind: ErrorKind::Incomplete,
+                    ..
+                }) => {}
+                result => panic!("Fixing {:?} as {:?} gave {:?}", line, fixed, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ambiguous_language() {
+        for pairs in [
+            [('(', ')', 1, 1), ('(', ']', 2, 2)],
+            [('(', ')', 1, 1), ('[', ')', 2, 2)],
+            [('(', ')', 1, 1), (')', '(', 2, 2)],
+            [('<', '>', 1, 1), ('>', ']', 2, 2)],
+            [('<', '>', 1, 1), ('[', '<', 2, 2)],
+        ] {
+            assert!(
+                std::panic::catch_unwind(|| BracketLanguage::new(&pairs, 5)).is_err(),
+                "{:?} should be ambiguous",
+                pairs
+            );
+        }
 
-        assert_eq!(get_completion_cost("<{([{{}}[<[[[<>{}]]]>[]]"), Ok(294));
+        BracketLanguage::new(&[('"', '"', 1, 1), ('(', ')', 2, 2)], 5);
     }
 }
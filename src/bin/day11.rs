@@ -1,6 +1,6 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 enum Octopus {
     Dim(u8),
     Flashing,
@@ -21,9 +21,8 @@ impl Octopus {
     }
 
     fn relax(&mut self) {
-        match self {
-            Octopus::Flashing => *self = Octopus::Flashed,
-            _ => {}
+        if self.is_flashing() {
+            *self = Octopus::Flashed
         }
     }
 
@@ -58,24 +57,18 @@ impl Cavern {
         self.octopuses[0].len()
     }
 
-    fn get_adjacent_points(&self, x: usize, y: usize) -> HashSet<(usize, usize)> {
-        let mut adjacents = HashSet::new();
-
-        let min_x = if x > 0 { x - 1 } else { x };
-        let max_x = if x < self.max_x() - 1 { x + 1 } else { x };
+    fn get_adjacent_points(&self, x: usize, y: usize) -> impl Iterator<Item = (usize, usize)> {
+        let min_x = x.saturating_sub(1);
+        let max_x = (x + 1).min(self.max_x() - 1);
 
-        let min_y = if y > 0 { y - 1 } else { y };
-        let max_y = if y < self.max_y() - 1 { y + 1 } else { y };
-
-        for adjacent_x in min_x..=max_x {
-            for adjacent_y in min_y..=max_y {
-                if adjacent_x != x || adjacent_y != y {
-                    adjacents.insert((adjacent_x, adjacent_y));
-                }
-            }
-        }
+        let min_y = y.saturating_sub(1);
+        let max_y = (y + 1).min(self.max_y() - 1);
 
-        adjacents
+        (min_x..=max_x)
+            .flat_map(move |adjacent_x| {
+                (min_y..=max_y).map(move |adjacent_y| (adjacent_x, adjacent_y))
+            })
+            .filter(move |&adjacent| adjacent != (x, y))
     }
 
     fn energise_all(&mut self) {
@@ -97,29 +90,31 @@ impl Cavern {
             .sum::<usize>();
     }
 
+    /// Each octopus can flash at most once per step, so each one is relaxed and passes on its energy
+    /// exactly once.
     fn process_flashing(mut self) -> Self {
-        let original = self.clone();
+        let mut flashing: Vec<(usize, usize)> = (0..self.max_x())
+            .flat_map(|x| (0..self.max_y()).map(move |y| (x, y)))
+            .filter(|&(x, y)| self.octopuses[x][y].is_flashing())
+            .collect();
 
-        for x in 0..self.max_x() {
-            for y in 0..self.max_y() {
-                let octopus = &mut self.octopuses[x][y];
+        while let Some((x, y)) = flashing.pop() {
+            self.octopuses[x][y].relax();
 
-                if octopus.is_flashing() {
-                    octopus.relax();
+            for (adjacent_x, adjacent_y) in self.get_adjacent_points(x, y) {
+                let adjacent = &mut self.octopuses[adjacent_x][adjacent_y];
+                let was_flashing = adjacent.is_flashing();
 
-                    for (adjacent_x, adjacent_y) in self.get_adjacent_points(x, y) {
-                        self.octopuses[adjacent_x][adjacent_y].energise()
-                    }
+                adjacent.energise();
+
+                if !was_flashing && adjacent.is_flashing() {
+                    flashing.push((adjacent_x, adjacent_y));
                 }
             }
         }
 
-        if self == original {
-            self.reset_all();
-            self
-        } else {
-            self.process_flashing()
-        }
+        self.reset_all();
+        self
     }
 
     fn step(mut self) -> Self {
@@ -136,21 +131,76 @@ impl Cavern {
         self
     }
 
-    fn steps_until_synchronised(mut self) -> u32 {
-        let mut previous_flashes = 0;
+    fn steps_until_synchronised(self) -> Option<u64> {
+        self.find_cycle().first_synchronised_step()
+    }
+
+    /// Steps the cavern until it revisits a state. The cavern is deterministic, so from then on it
+    /// repeats forever.
+    fn find_cycle(mut self) -> CycleReport {
+        let cavern_size = self.max_x() * self.max_y();
+        let mut seen = HashMap::new();
+        let mut flash_counts = vec![self.flash_count as u64];
+        let mut synchronised_steps = Vec::new();
         let mut step = 0;
 
         loop {
-            if self.flash_count == self.max_x() * self.max_y() + previous_flashes {
-                return step;
+            if let Some(&start) = seen.get(&self.octopuses) {
+                return CycleReport {
+                    start,
+                    length: step - start,
+                    flash_counts,
+                    synchronised_steps,
+                };
             }
-            previous_flashes = self.flash_count;
-            step += 1;
+            seen.insert(self.octopuses.clone(), step);
+
+            let previous_flashes = self.flash_count;
             self = self.step();
+            step += 1;
+
+            flash_counts.push(self.flash_count as u64);
+            if self.flash_count - previous_flashes == cavern_size {
+                synchronised_steps.push(step);
+            }
         }
     }
 }
 
+#[derive(Debug, PartialEq)]
+struct CycleReport {
+    /// The first step whose state recurs.
+    start: u64,
+    length: u64,
+    /// The total number of flashes after each step, up to the end of the first pass round the cycle.
+    flash_counts: Vec<u64>,
+    synchronised_steps: Vec<u64>,
+}
+
+impl CycleReport {
+    #[allow(dead_code)]
+    fn flashes_after(&self, steps: u64) -> u64 {
+        if steps <= self.start + self.length {
+            return self.flash_counts[steps as usize];
+        }
+
+        let flashes_before_cycle = self.flash_counts[self.start as usize];
+        let flashes_per_cycle =
+            self.flash_counts[(self.start + self.length) as usize] - flashes_before_cycle;
+
+        let cycles = (steps - self.start) / self.length;
+        let remainder = (steps - self.start) % self.length;
+
+        cycles * flashes_per_cycle + self.flash_counts[(self.start + remainder) as usize]
+    }
+
+    /// Every state the cavern will ever be in has been seen by the end of the first cycle, so if it
+    /// hasn't synchronised by then, it never will.
+    fn first_synchronised_step(&self) -> Option<u64> {
+        self.synchronised_steps.first().copied()
+    }
+}
+
 impl From<String> for Cavern {
     fn from(input: String) -> Cavern {
         Cavern {
@@ -177,16 +227,43 @@ fn main() {
         cavern.clone().step_times(100).flash_count
     );
 
-    println!(
-        "Synchronises after {} steps",
-        cavern.steps_until_synchronised()
-    );
+    match cavern.steps_until_synchronised() {
+        Some(steps) => println!("Synchronises after {} steps", steps),
+        None => println!("Never synchronises"),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// The original implementation, which rescans the whole cavern until nothing changes.
+    fn step_by_rescanning(mut cavern: Cavern) -> Cavern {
+        cavern.energise_all();
+
+        loop {
+            let original = cavern.clone();
+
+            for x in 0..cavern.max_x() {
+                for y in 0..cavern.max_y() {
+                    if cavern.octopuses[x][y].is_flashing() {
+                        cavern.octopuses[x][y].relax();
+
+                        let adjacents: Vec<_> = cavern.get_adjacent_points(x, y).collect();
+                        for (adjacent_x, adjacent_y) in adjacents {
+                            cavern.octopuses[adjacent_x][adjacent_y].energise()
+                        }
+                    }
+                }
+            }
+
+            if cavern == original {
+                cavern.reset_all();
+                return cavern;
+            }
+        }
+    }
+
     #[test]
     fn test_parse() {
         assert_eq!(
@@ -297,4 +374,80 @@ mod tests {
         );
         assert_eq!(cavern.flash_count, 1656);
     }
+
+    #[test]
+    fn test_matches_rescanning_step() {
+        for fixture in [
+            "12\n34",
+            "98\n34",
+            "11111\n19991\n19191\n19991\n11111",
+            "5483143223
+2745854711
+5264556173
+6141336146
+6357385478
+4167524645
+2176841721
+6882881134
+4846848554
+5283751526",
+        ] {
+            let mut expected = Cavern::from(fixture.to_string());
+            let mut actual = expected.clone();
+
+            for _ in 0..200 {
+                expected = step_by_rescanning(expected);
+                actual = actual.step();
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_example_cycle() {
+        let cavern = Cavern::from(
+            "5483143223
+2745854711
+5264556173
+6141336146
+6357385478
+4167524645
+2176841721
+6882881134
+4846848554
+5283751526"
+                .to_string(),
+        );
+
+        assert_eq!(cavern.clone().steps_until_synchronised(), Some(195));
+
+        let report = cavern.clone().find_cycle();
+        assert_eq!(report.length, 10);
+        assert_eq!(report.flashes_after(100), 1656);
+        assert_eq!(
+            report.flashes_after(1000),
+            cavern.clone().step_times(1000).flash_count as u64
+        );
+        assert_eq!(
+            report.flashes_after(1_000_000_000_000),
+            report.flashes_after(195) + (1_000_000_000_000 - 195) / 10 * 100
+        );
+    }
+
+    #[test]
+    fn test_never_synchronises() {
+        let report = Cavern::from("0000000000".to_string()).find_cycle();
+
+        assert_eq!(report.first_synchronised_step(), Some(10));
+
+        let report = Cavern::from("0\n5".to_string()).find_cycle();
+
+        assert_eq!(report.first_synchronised_step(), None);
+        assert_eq!(
+            report.flashes_after(1000),
+            Cavern::from("0\n5".to_string())
+                .step_times(1000)
+                .flash_count as u64
+        );
+    }
 }
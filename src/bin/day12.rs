@@ -1,4 +1,5 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Write};
 
 struct CaveNetwork(HashMap<String, Node>);
 
@@ -18,92 +19,417 @@ impl CaveNetwork {
             .add_adjacent(node_a);
     }
 
-    fn extend_paths(&self, input_path: Vec<String>) -> Vec<Vec<String>> {
-        let current_node = &self.0[input_path.last().unwrap()];
+    /// Builds the network without checking that it has finitely many paths. Use `paths_up_to` to explore
+    /// networks that don't.
+    fn from_unchecked(input: &str) -> Self {
+        let mut cave_network = CaveNetwork::new();
+
+        for connection in input.split('\n') {
+            let mut nodes = connection.split('-');
+            let node_a = nodes.next().unwrap();
+            let node_b = nodes.next().unwrap();
+
+            cave_network.add_node_pair(node_a, node_b);
+        }
+
+        cave_network
+    }
 
-        match current_node {
+    /// Big caves can be revisited without limit, so two adjacent big caves let a path bounce between them
+    /// forever.
+    fn find_big_cave_loop(&self) -> Option<(String, String)> {
+        let mut big_caves: Vec<&String> = self
+            .0
+            .iter()
+            .filter(|(_, node)| matches!(node, Node::BigCave { .. }))
+            .map(|(name, _)| name)
+            .collect();
+        big_caves.sort();
+
+        big_caves.into_iter().find_map(|cave| {
+            self.adjacents_of(cave)
+                .into_iter()
+                .find(|adjacent| matches!(self.0[*adjacent], Node::BigCave { .. }))
+                .map(|adjacent| (cave.to_string(), adjacent.to_string()))
+        })
+    }
+
+    #[allow(dead_code)]
+    fn paths(&self, policy: VisitPolicy) -> Paths<'_> {
+        Paths::new(self, policy, None)
+    }
+
+    /// Only yields paths that pass through at most `max_length` caves, including start and end.
+    #[allow(dead_code)]
+    fn paths_up_to(&self, policy: VisitPolicy, max_length: usize) -> Paths<'_> {
+        Paths::new(self, policy, Some(max_length))
+    }
+
+    fn adjacents_of(&self, cave: &str) -> Vec<&str> {
+        let mut adjacents: Vec<&str> = match &self.0[cave] {
             Node::Start { adjacents }
             | Node::BigCave { adjacents }
-            | Node::SmallCave { adjacents } => adjacents
-                .iter()
-                .filter(|next_node| match &self.0[*next_node] {
-                    Node::BigCave { .. } | Node::End => true,
-                    Node::Start { .. } => false,
-                    Node::SmallCave { .. } => !input_path.contains(next_node),
-                })
-                .flat_map(|a| {
-                    let mut path = input_path.clone();
-                    path.push(a.to_string());
-                    self.extend_paths(path)
-                })
-                .collect(),
-            Node::End => vec![input_path],
+            | Node::SmallCave { adjacents } => adjacents.iter().map(String::as_str).collect(),
+            Node::End => vec![],
+        };
+        adjacents.sort_unstable();
+        adjacents
+    }
+
+    /// Renders the network in Graphviz DOT format. The caves and connections along `highlighted_path`, if
+    /// given, are drawn in red.
+    #[allow(dead_code)]
+    fn to_dot(&self, highlighted_path: Option<&[String]>) -> String {
+        let highlighted_path = highlighted_path.unwrap_or(&[]);
+        let highlighted_edges: HashSet<(&str, &str)> = highlighted_path
+            .windows(2)
+            .map(|pair| {
+                let (a, b) = (pair[0].as_str(), pair[1].as_str());
+                (a.min(b), a.max(b))
+            })
+            .collect();
+
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+
+        let mut dot = String::from("graph caves {\n");
+
+        for name in &names {
+            let shape = match self.0[*name] {
+                Node::Start { .. } => "invtriangle",
+                Node::End => "triangle",
+                Node::BigCave { .. } => "box",
+                Node::SmallCave { .. } => "ellipse",
+            };
+            let colour = if highlighted_path.contains(name) {
+                ", color=red"
+            } else {
+                ""
+            };
+
+            writeln!(dot, "    \"{}\" [shape={}{}];", name, shape, colour).unwrap();
         }
+
+        // The end cave doesn't record its adjacents, so edges are collected from both ends and deduplicated.
+        let edges: BTreeSet<(&str, &str)> = names
+            .iter()
+            .flat_map(|name| {
+                self.adjacents_of(name)
+                    .into_iter()
+                    .map(move |adjacent| (name.as_str().min(adjacent), name.as_str().max(adjacent)))
+            })
+            .collect();
+
+        for edge in edges {
+            let colour = if highlighted_edges.contains(&edge) {
+                " [color=red, penwidth=2]"
+            } else {
+                ""
+            };
+
+            writeln!(dot, "    \"{}\" -- \"{}\"{};", edge.0, edge.1, colour).unwrap();
+        }
+
+        dot.push_str("}\n");
+        dot
     }
 
     fn get_path_count(&self) -> usize {
-        self.extend_paths(vec!["start".to_string()]).len()
+        InternedNetwork::from(self).count_paths(false) as usize
     }
 
-    fn path_has_small_cave_repeat(&self, input_path: &[String]) -> bool {
-        for i in 0..input_path.len() {
-            let element = &self.0[&input_path[i]];
+    fn get_path_with_one_repeat_count(&self) -> usize {
+        InternedNetwork::from(self).count_paths(true) as usize
+    }
+}
 
-            match element {
-                Node::BigCave { .. } | Node::End | Node::Start { .. } => continue,
-                Node::SmallCave { .. } => {
-                    if input_path[i + 1..].contains(&input_path[i]) {
-                        return true;
-                    }
+/// Limits on which caves a path may pass through, and how often. Big caves may always be revisited.
+#[derive(Debug, Clone, PartialEq)]
+struct VisitPolicy {
+    max_visits_per_small_cave: usize,
+    /// The number of visits to small caves that may be repeats of earlier visits, across all small caves.
+    max_total_repeats: usize,
+    forbidden: HashSet<String>,
+}
+
+#[allow(dead_code)]
+impl VisitPolicy {
+    fn no_repeats() -> Self {
+        VisitPolicy {
+            max_visits_per_small_cave: 1,
+            max_total_repeats: 0,
+            forbidden: HashSet::new(),
+        }
+    }
+
+    fn one_repeat() -> Self {
+        VisitPolicy {
+            max_visits_per_small_cave: 2,
+            max_total_repeats: 1,
+            forbidden: HashSet::new(),
+        }
+    }
+
+    fn forbidding(mut self, caves: &[&str]) -> Self {
+        self.forbidden
+            .extend(caves.iter().map(|cave| cave.to_string()));
+        self
+    }
+}
+
+/// A depth-first walk over every path from start to end, yielding each one as it's found.
+struct Paths<'a> {
+    cave_network: &'a CaveNetwork,
+    policy: VisitPolicy,
+    path: Vec<&'a str>,
+    /// For each cave on the path, the adjacent caves still to be tried after it.
+    to_try: Vec<Vec<&'a str>>,
+    small_cave_visits: HashMap<&'a str, usize>,
+    repeats: usize,
+    max_length: Option<usize>,
+}
+
+impl<'a> Paths<'a> {
+    fn new(cave_network: &'a CaveNetwork, policy: VisitPolicy, max_length: Option<usize>) -> Self {
+        let mut paths = Paths {
+            cave_network,
+            policy,
+            path: vec![],
+            to_try: vec![],
+            small_cave_visits: HashMap::new(),
+            repeats: 0,
+            max_length,
+        };
+
+        if cave_network.0.contains_key("start") {
+            paths.path.push("start");
+            paths.to_try.push(cave_network.adjacents_of("start"));
+        }
+
+        paths
+    }
+
+    fn can_visit(&self, cave: &str) -> bool {
+        if self.policy.forbidden.contains(cave) {
+            return false;
+        }
+
+        let is_end = matches!(self.cave_network.0[cave], Node::End);
+
+        // Any cave other than the end needs room for at least the end after it.
+        let length_after_visit = self.path.len() + if is_end { 1 } else { 2 };
+        if self
+            .max_length
+            .is_some_and(|max_length| length_after_visit > max_length)
+        {
+            return false;
+        }
+
+        match self.cave_network.0[cave] {
+            Node::Start { .. } => false,
+            Node::End | Node::BigCave { .. } => true,
+            Node::SmallCave { .. } => {
+                let visits = self.small_cave_visits.get(cave).copied().unwrap_or(0);
+
+                visits < self.policy.max_visits_per_small_cave
+                    && (visits == 0 || self.repeats < self.policy.max_total_repeats)
+            }
+        }
+    }
+
+    fn visit(&mut self, cave: &'a str) {
+        if let Node::SmallCave { .. } = self.cave_network.0[cave] {
+            let visits = self.small_cave_visits.entry(cave).or_insert(0);
+            if *visits > 0 {
+                self.repeats += 1;
+            }
+            *visits += 1;
+        }
+
+        self.path.push(cave);
+        self.to_try.push(self.cave_network.adjacents_of(cave));
+    }
+
+    fn backtrack(&mut self) {
+        self.to_try.pop();
+
+        if let Some(cave) = self.path.pop() {
+            if let Some(visits) = self.small_cave_visits.get_mut(cave) {
+                *visits -= 1;
+                if *visits > 0 {
+                    self.repeats -= 1;
                 }
             }
         }
-        false
     }
+}
 
-    fn extend_paths_with_one_repeat(&self, input_path: Vec<String>) -> Vec<Vec<String>> {
-        let current_node = &self.0[input_path.last().unwrap()];
-        let path_repeats = self.path_has_small_cave_repeat(&input_path);
+impl<'a> Iterator for Paths<'a> {
+    type Item = Vec<String>;
 
-        match current_node {
-            Node::Start { adjacents }
-            | Node::BigCave { adjacents }
-            | Node::SmallCave { adjacents } => adjacents
-                .iter()
-                .filter(|next_node| match &self.0[*next_node] {
-                    Node::BigCave { .. } | Node::End => true,
-                    Node::Start { .. } => false,
-                    Node::SmallCave { .. } => !(input_path.contains(next_node) && path_repeats),
-                })
-                .flat_map(|a| {
-                    let mut path = input_path.clone();
-                    path.push(a.to_string());
-                    self.extend_paths_with_one_repeat(path)
-                })
-                .collect(),
-            Node::End => vec![input_path],
+    fn next(&mut self) -> Option<Vec<String>> {
+        loop {
+            let next_cave = match self.to_try.last_mut()?.pop() {
+                Some(next_cave) => next_cave,
+                None => {
+                    self.backtrack();
+                    continue;
+                }
+            };
+
+            if !self.can_visit(next_cave) {
+                continue;
+            }
+
+            if let Node::End = self.cave_network.0[next_cave] {
+                return Some(
+                    self.path
+                        .iter()
+                        .chain(std::iter::once(&next_cave))
+                        .map(|cave| cave.to_string())
+                        .collect(),
+                );
+            }
+
+            self.visit(next_cave);
         }
     }
+}
 
-    fn get_path_with_one_repeat_count(&self) -> usize {
-        self.extend_paths_with_one_repeat(vec!["start".to_string()])
-            .len()
+/// The cave network with each cave replaced by an index, and each small cave given a bit in a visited mask.
+/// This lets paths be counted without ever being built.
+struct InternedNetwork {
+    start: usize,
+    end: usize,
+    adjacents: Vec<Vec<usize>>,
+    small_cave_bits: Vec<Option<u64>>,
+}
+
+impl InternedNetwork {
+    fn count_paths(&self, allow_one_repeat: bool) -> u64 {
+        let mut memo = HashMap::new();
+
+        self.count_paths_from(self.start, 0, !allow_one_repeat, &mut memo)
+    }
+
+    fn count_paths_from(
+        &self,
+        node: usize,
+        visited: u64,
+        repeat_used: bool,
+        memo: &mut HashMap<(usize, u64, bool), u64>,
+    ) -> u64 {
+        if node == self.end {
+            return 1;
+        }
+
+        if let Some(&count) = memo.get(&(node, visited, repeat_used)) {
+            return count;
+        }
+
+        let mut count = 0;
+
+        for &next_node in &self.adjacents[node] {
+            if next_node == self.start {
+                continue;
+            }
+
+            count += match self.small_cave_bits[next_node] {
+                None => self.count_paths_from(next_node, visited, repeat_used, memo),
+                Some(bit) if visited & bit == 0 => {
+                    self.count_paths_from(next_node, visited | bit, repeat_used, memo)
+                }
+                Some(_) if !repeat_used => self.count_paths_from(next_node, visited, true, memo),
+                Some(_) => 0,
+            };
+        }
+
+        memo.insert((node, visited, repeat_used), count);
+        count
     }
 }
 
-impl From<&str> for CaveNetwork {
-    fn from(input: &str) -> CaveNetwork {
-        let mut cave_network = CaveNetwork::new();
+impl From<&CaveNetwork> for InternedNetwork {
+    fn from(cave_network: &CaveNetwork) -> InternedNetwork {
+        if let Some((cave_a, cave_b)) = cave_network.find_big_cave_loop() {
+            panic!(
+                "Unable to count paths: {}",
+                CaveNetworkError::BigCaveLoop(cave_a, cave_b)
+            );
+        }
 
-        for connection in input.split('\n') {
-            let mut nodes = connection.split('-');
-            let node_a = nodes.next().unwrap();
-            let node_b = nodes.next().unwrap();
+        let mut names: Vec<&String> = cave_network.0.keys().collect();
+        names.sort();
 
-            cave_network.add_node_pair(node_a, node_b);
+        let ids: HashMap<&String, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, &name)| (name, i))
+            .collect();
+
+        let mut small_cave_count = 0;
+        let small_cave_bits = names
+            .iter()
+            .map(|name| match cave_network.0[*name] {
+                Node::SmallCave { .. } => {
+                    assert!(small_cave_count < 64, "Too many small caves to count paths");
+                    small_cave_count += 1;
+                    Some(1 << (small_cave_count - 1))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let adjacents = names
+            .iter()
+            .map(|name| match &cave_network.0[*name] {
+                Node::Start { adjacents }
+                | Node::BigCave { adjacents }
+                | Node::SmallCave { adjacents } => {
+                    let mut adjacents: Vec<usize> = adjacents.iter().map(|a| ids[a]).collect();
+                    adjacents.sort();
+                    adjacents
+                }
+                Node::End => vec![],
+            })
+            .collect();
+
+        InternedNetwork {
+            start: ids[&"start".to_string()],
+            end: ids[&"end".to_string()],
+            adjacents,
+            small_cave_bits,
         }
+    }
+}
 
-        cave_network
+#[derive(Debug, PartialEq)]
+enum CaveNetworkError {
+    BigCaveLoop(String, String),
+}
+
+impl fmt::Display for CaveNetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaveNetworkError::BigCaveLoop(cave_a, cave_b) => write!(
+                f,
+                "big caves {} and {} are adjacent, so paths can loop {}-{}-{} forever",
+                cave_a, cave_b, cave_a, cave_b, cave_a
+            ),
+        }
+    }
+}
+
+impl TryFrom<&str> for CaveNetwork {
+    type Error = CaveNetworkError;
+
+    fn try_from(input: &str) -> Result<CaveNetwork, CaveNetworkError> {
+        let cave_network = CaveNetwork::from_unchecked(input);
+
+        match cave_network.find_big_cave_loop() {
+            Some((cave_a, cave_b)) => Err(CaveNetworkError::BigCaveLoop(cave_a, cave_b)),
+            None => Ok(cave_network),
+        }
     }
 }
 
@@ -148,7 +474,10 @@ impl From<&str> for Node {
 
 fn main() {
     let data = std::fs::read_to_string("data/day12.txt").unwrap();
-    let cave_network = CaveNetwork::from(data.as_str());
+    let cave_network = match CaveNetwork::try_from(data.as_str()) {
+        Ok(cave_network) => cave_network,
+        Err(e) => panic!("Invalid cave network: {}", e),
+    };
 
     println!("{} possible paths", cave_network.get_path_count());
     println!(
@@ -167,10 +496,7 @@ mod tests {
 
         cave_network.add_node_pair("start", "end");
 
-        assert_eq!(
-            cave_network.extend_paths(vec!["start".to_string()]).len(),
-            1,
-        );
+        assert_eq!(cave_network.paths(VisitPolicy::no_repeats()).count(), 1);
     }
 
     #[test]
@@ -195,7 +521,7 @@ mod tests {
 
     #[test]
     fn test_small_example() {
-        let cave_network = CaveNetwork::from(
+        let cave_network = CaveNetwork::try_from(
             "start-A
 start-b
 A-c
@@ -203,7 +529,8 @@ A-b
 b-d
 A-end
 b-end",
-        );
+        )
+        .unwrap();
 
         assert_eq!(cave_network.get_path_count(), 10);
         assert_eq!(cave_network.get_path_with_one_repeat_count(), 36);
@@ -211,7 +538,7 @@ b-end",
 
     #[test]
     fn test_big_example() {
-        let cave_network = CaveNetwork::from(
+        let cave_network = CaveNetwork::try_from(
             "fs-end
 he-DX
 fs-he
@@ -230,9 +557,218 @@ he-WI
 zg-he
 pj-fs
 start-RW",
-        );
+        )
+        .unwrap();
 
         assert_eq!(cave_network.get_path_count(), 226);
         assert_eq!(cave_network.get_path_with_one_repeat_count(), 3509);
     }
+
+    #[test]
+    fn test_counts_match_enumeration() {
+        let cave_network = CaveNetwork::try_from(
+            "dc-end
+HN-start
+start-kj
+dc-start
+dc-HN
+LN-dc
+HN-end
+kj-sj
+kj-HN
+kj-dc",
+        )
+        .unwrap();
+
+        assert_eq!(
+            cave_network.get_path_count(),
+            cave_network.paths(VisitPolicy::no_repeats()).count()
+        );
+        assert_eq!(
+            cave_network.get_path_with_one_repeat_count(),
+            cave_network.paths(VisitPolicy::one_repeat()).count()
+        );
+        assert_eq!(cave_network.get_path_count(), 19);
+        assert_eq!(cave_network.get_path_with_one_repeat_count(), 103);
+    }
+
+    #[test]
+    fn test_visit_policies() {
+        let cave_network = CaveNetwork::try_from(
+            "start-A
+start-b
+A-c
+A-b
+b-d
+A-end
+b-end",
+        )
+        .unwrap();
+
+        let mut paths: Vec<String> = cave_network
+            .paths(VisitPolicy::no_repeats().forbidding(&["c"]))
+            .map(|path| path.join(","))
+            .collect();
+        paths.sort();
+
+        assert_eq!(
+            paths,
+            vec![
+                "start,A,b,A,end",
+                "start,A,b,end",
+                "start,A,end",
+                "start,b,A,end",
+                "start,b,end",
+            ]
+        );
+
+        let any_cave_twice = VisitPolicy {
+            max_visits_per_small_cave: 2,
+            max_total_repeats: usize::MAX,
+            forbidden: HashSet::new(),
+        };
+        let paths: Vec<_> = cave_network.paths(any_cave_twice).collect();
+
+        assert!(paths
+            .iter()
+            .any(|path| path.iter().filter(|cave| *cave == "b").count() == 2
+                && path.iter().filter(|cave| *cave == "c").count() == 2));
+        assert_eq!(paths.len(), 54);
+
+        let count_paths = |max_visits_per_small_cave, max_total_repeats| {
+            cave_network
+                .paths(VisitPolicy {
+                    max_visits_per_small_cave,
+                    max_total_repeats,
+                    forbidden: HashSet::new(),
+                })
+                .count()
+        };
+
+        assert_eq!(count_paths(1, 0), 10);
+        assert_eq!(count_paths(2, 1), 36);
+        assert_eq!(count_paths(2, 2), 54);
+        assert_eq!(count_paths(3, 2), 101);
+        assert_eq!(count_paths(3, usize::MAX), 278);
+
+        let paths: Vec<_> = cave_network
+            .paths(VisitPolicy {
+                max_visits_per_small_cave: 3,
+                max_total_repeats: 2,
+                forbidden: HashSet::new(),
+            })
+            .collect();
+        assert!(paths.iter().all(|path| {
+            let small_caves = path[1..path.len() - 1]
+                .iter()
+                .filter(|cave| cave.chars().all(|c| c.is_ascii_lowercase()));
+            let distinct: HashSet<_> = small_caves.clone().collect();
+
+            small_caves.count() - distinct.len() <= 2
+        }));
+    }
+
+    #[test]
+    fn test_big_cave_loop() {
+        let input = "start-A
+A-B
+B-end";
+
+        assert_eq!(
+            CaveNetwork::try_from(input).err(),
+            Some(CaveNetworkError::BigCaveLoop(
+                "A".to_string(),
+                "B".to_string()
+            ))
+        );
+        assert_eq!(
+            CaveNetworkError::BigCaveLoop("A".to_string(), "B".to_string()).to_string(),
+            "big caves A and B are adjacent, so paths can loop A-B-A forever"
+        );
+
+        let cave_network = CaveNetwork::from_unchecked(input);
+        let mut paths: Vec<String> = cave_network
+            .paths_up_to(VisitPolicy::no_repeats(), 6)
+            .map(|path| path.join(","))
+            .collect();
+        paths.sort();
+
+        assert_eq!(paths, vec!["start,A,B,A,B,end", "start,A,B,end"]);
+    }
+
+    #[test]
+    fn test_paths_up_to() {
+        let cave_network = CaveNetwork::try_from(
+            "start-A
+start-b
+A-c
+A-b
+b-d
+A-end
+b-end",
+        )
+        .unwrap();
+
+        assert_eq!(
+            cave_network
+                .paths_up_to(VisitPolicy::no_repeats(), 2)
+                .count(),
+            0
+        );
+        assert_eq!(
+            cave_network
+                .paths_up_to(VisitPolicy::no_repeats(), 3)
+                .count(),
+            2
+        );
+        assert_eq!(
+            cave_network
+                .paths_up_to(VisitPolicy::no_repeats(), 100)
+                .count(),
+            10
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let cave_network = CaveNetwork::try_from(
+            "start-A
+A-b
+A-end",
+        )
+        .unwrap();
+
+        assert_eq!(
+            cave_network.to_dot(None),
+            r#"graph caves {
+    "A" [shape=box];
+    "b" [shape=ellipse];
+    "end" [shape=triangle];
+    "start" [shape=invtriangle];
+    "A" -- "b";
+    "A" -- "end";
+    "A" -- "start";
+}
+"#
+        );
+
+        let path: Vec<String> = ["start", "A", "end"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+
+        assert_eq!(
+            cave_network.to_dot(Some(&path)),
+            r#"graph caves {
+    "A" [shape=box, color=red];
+    "b" [shape=ellipse];
+    "end" [shape=triangle, color=red];
+    "start" [shape=invtriangle, color=red];
+    "A" -- "b";
+    "A" -- "end" [color=red, penwidth=2];
+    "A" -- "start" [color=red, penwidth=2];
+}
+"#
+        );
+    }
 }
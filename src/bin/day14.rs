@@ -1,7 +1,9 @@
 use lazy_static::lazy_static;
+use num_bigint::BigUint;
 use regex::Regex;
 
 use std::collections::HashMap;
+use std::fmt;
 
 lazy_static! {
     static ref RULE_REGEX: Regex = Regex::new(r"^(.)(.) -> (.)$").unwrap();
@@ -10,49 +12,107 @@ lazy_static! {
 #[derive(Debug, PartialEq)]
 struct PolymerisationRules(HashMap<(char, char), char>);
 
-impl From<String> for PolymerisationRules {
-    fn from(input: String) -> PolymerisationRules {
-        PolymerisationRules(
-            input
-                .split('\n')
-                .map(|s| {
-                    let mut chars = s.chars();
+#[derive(Debug, PartialEq)]
+struct MalformedRule {
+    line_number: usize,
+    line: String,
+}
 
-                    let a = chars.next().unwrap();
-                    let b = chars.next().unwrap();
+impl TryFrom<String> for PolymerisationRules {
+    type Error = MalformedRule;
 
-                    ((a, b), chars.last().unwrap())
-                })
-                .collect(),
-        )
+    fn try_from(input: String) -> Result<PolymerisationRules, MalformedRule> {
+        input
+            .split('\n')
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(i, line)| {
+                let captures = RULE_REGEX.captures(line).ok_or_else(|| MalformedRule {
+                    line_number: i + 1,
+                    line: line.to_string(),
+                })?;
+                let element = |group: usize| captures[group].chars().next().unwrap();
+
+                Ok(((element(1), element(2)), element(3)))
+            })
+            .collect::<Result<_, _>>()
+            .map(PolymerisationRules)
     }
 }
 
 impl PolymerisationRules {
-    fn apply(&self, a: char, b: char) -> char {
-        self.0.get(&(a, b)).unwrap().clone()
+    /// Pairs without a rule are left alone.
+    fn apply(&self, a: char, b: char) -> Option<char> {
+        self.0.get(&(a, b)).copied()
+    }
+
+    /// The pairs that replace the given one after a step.
+    fn products(&self, pair: Pair) -> Vec<Pair> {
+        match self.apply(pair.0, pair.1) {
+            Some(middle) => vec![(pair.0, middle), (middle, pair.1)],
+            None => vec![pair],
+        }
     }
 }
 
+/// The whole polymer, expanded in full. This grows exponentially, so it's only suitable for small step counts,
+/// but it's simple enough to act as a reference for `BulkPolymer`.
 #[derive(Debug, PartialEq)]
 struct Polymer(Vec<char>);
 
+impl From<&str> for Polymer {
+    fn from(input: &str) -> Polymer {
+        Polymer(input.chars().collect())
+    }
+}
+
+impl fmt::Display for Polymer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.iter().collect::<String>())
+    }
+}
+
+#[allow(dead_code)]
 impl Polymer {
-    #[allow(dead_code)]
     fn step(&mut self, rules: &PolymerisationRules) {
-        for i in 0..self.0.len() - 1 {
-            let a = self.0[2 * i];
-            let b = self.0[2 * i + 1];
+        let mut expanded = Vec::with_capacity(self.0.len() * 2);
+
+        for window in self.0.windows(2) {
+            expanded.push(window[0]);
+            expanded.extend(rules.apply(window[0], window[1]));
+        }
+        expanded.extend(self.0.last());
+
+        self.0 = expanded;
+    }
+
+    fn step_times(&mut self, rules: &PolymerisationRules, times: u32) {
+        for _ in 0..times {
+            self.step(rules);
+        }
+    }
+
+    fn element_counts(&self) -> HashMap<char, u64> {
+        let mut counts = HashMap::new();
 
-            self.0.insert(2 * i + 1, rules.apply(a, b));
+        for element in &self.0 {
+            *counts.entry(*element).or_insert(0) += 1;
         }
+
+        counts
     }
 }
 
 type Pair = (char, char);
 
+/// Tracks only how many of each pair are present. The first and last elements never change, and are kept so
+/// that the pair counts can be turned back into element counts.
 #[derive(Debug, PartialEq)]
-struct BulkPolymer(HashMap<Pair, u64>);
+struct BulkPolymer {
+    pairs: HashMap<Pair, u64>,
+    first: Option<char>,
+    last: Option<char>,
+}
 
 impl From<String> for BulkPolymer {
     fn from(input: String) -> BulkPolymer {
@@ -71,7 +131,11 @@ impl From<String> for BulkPolymer {
                     .or_insert(0) += 1
             });
 
-        BulkPolymer(counter)
+        BulkPolymer {
+            pairs: counter,
+            first: input.chars().next(),
+            last: input.chars().last(),
+        }
     }
 }
 
@@ -79,14 +143,35 @@ impl BulkPolymer {
     fn step(&mut self, rules: &PolymerisationRules) {
         let mut new_counter = HashMap::new();
 
-        for (pair, count) in &self.0 {
-            let middle = rules.apply(pair.0, pair.1);
+        for (pair, count) in &self.pairs {
+            for product in rules.products(*pair) {
+                *new_counter.entry(product).or_insert(0) += count;
+            }
+        }
+
+        self.pairs = new_counter;
+    }
 
-            *new_counter.entry((pair.0, middle)).or_insert(0) += count;
-            *new_counter.entry((middle, pair.1)).or_insert(0) += count;
+    /// Every pair that is present now, or could appear in a later step, but has no rule.
+    fn missing_rules(&self, rules: &PolymerisationRules) -> Vec<Pair> {
+        let mut reachable: Vec<Pair> = self.pairs.keys().copied().collect();
+
+        let mut i = 0;
+        while i < reachable.len() {
+            for product in rules.products(reachable[i]) {
+                if !reachable.contains(&product) {
+                    reachable.push(product);
+                }
+            }
+            i += 1;
         }
 
-        self.0 = new_counter;
+        let mut missing: Vec<Pair> = reachable
+            .into_iter()
+            .filter(|pair| rules.apply(pair.0, pair.1).is_none())
+            .collect();
+        missing.sort();
+        missing
     }
 
     fn step_times(&mut self, rules: &PolymerisationRules, times: u32) {
@@ -95,21 +180,187 @@ impl BulkPolymer {
         }
     }
 
-    fn count_elements(&self) {
-        let mut char_counter = HashMap::new();
+    /// Every element is in two pairs, apart from the first and last, which are in one each.
+    fn element_counts(&self) -> HashMap<char, u64> {
+        let mut doubled_counts = HashMap::new();
+
+        for ((a, b), count) in &self.pairs {
+            *doubled_counts.entry(*a).or_insert(0) += count;
+            *doubled_counts.entry(*b).or_insert(0) += count;
+        }
 
-        for ((a, b), count) in &self.0 {
-            *char_counter.entry(a).or_insert(0) += count;
-            *char_counter.entry(b).or_insert(0) += count;
+        for end in self.first.iter().chain(self.last.iter()) {
+            *doubled_counts.entry(*end).or_insert(0) += 1;
         }
 
-        let min = char_counter.iter().min_by_key(|&(_, count)| count).unwrap();
-        let max = char_counter.iter().max_by_key(|&(_, count)| count).unwrap();
+        doubled_counts
+            .into_iter()
+            .map(|(element, count)| (element, count / 2))
+            .collect()
+    }
+
+    fn least_common_element(&self) -> Option<(char, u64)> {
+        self.element_counts()
+            .into_iter()
+            .min_by_key(|&(element, count)| (count, element))
+    }
 
-        // We're technically double-counting the first and last characters in the string. However, thanks to rounding during the division, it all washes out.
-        println!("Min: {} x {}", min.0, min.1 / 2);
-        println!("max: {} x {}", max.0, max.1 / 2);
-        println!("Difference: {}", max.1 / 2 - min.1 / 2);
+    fn most_common_element(&self) -> Option<(char, u64)> {
+        self.element_counts()
+            .into_iter()
+            .max_by_key(|&(element, count)| (count, element))
+    }
+
+    fn most_minus_least_common(&self) -> u64 {
+        match (self.most_common_element(), self.least_common_element()) {
+            (Some((_, most)), Some((_, least))) => most - least,
+            _ => 0,
+        }
+    }
+}
+
+type Matrix<T> = Vec<Vec<T>>;
+
+/// `mul_add(acc, a, b)` must return `acc + a * b`, reduced however the number type requires.
+fn multiply<T: Clone + From<u8>>(
+    a: &Matrix<T>,
+    b: &Matrix<T>,
+    mul_add: &impl Fn(T, &T, &T) -> T,
+) -> Matrix<T> {
+    (0..a.len())
+        .map(|row| {
+            (0..b[0].len())
+                .map(|column| {
+                    (0..b.len()).fold(T::from(0), |acc, i| mul_add(acc, &a[row][i], &b[i][column]))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The polymer's pair counts, and a matrix describing how one step of insertion turns each pair into two
+/// others. Repeated squaring of the matrix then skips any number of steps in logarithmic time.
+///
+/// `main` only needs the 40 steps `BulkPolymer` manages directly, so for now this is only used by the tests.
+#[allow(dead_code)]
+struct PairTransitions {
+    pairs: Vec<Pair>,
+    /// `matrix[to][from]` is how many `to` pairs a single `from` pair becomes after one step.
+    matrix: Matrix<u8>,
+    initial_counts: Vec<u64>,
+    last: Option<char>,
+}
+
+#[allow(dead_code)]
+impl PairTransitions {
+    fn new(polymer: &BulkPolymer, rules: &PolymerisationRules) -> Self {
+        let mut pairs: Vec<Pair> = polymer.pairs.keys().copied().collect();
+        let mut index: HashMap<Pair, usize> = HashMap::new();
+        let mut produces = vec![];
+
+        let mut i = 0;
+        while i < pairs.len() {
+            index.insert(pairs[i], i);
+
+            let products = rules.products(pairs[i]);
+            for product in &products {
+                if !pairs.contains(product) {
+                    pairs.push(*product);
+                }
+            }
+            produces.push(products);
+
+            i += 1;
+        }
+
+        let mut matrix = vec![vec![0; pairs.len()]; pairs.len()];
+        for (from, produced) in produces.iter().enumerate() {
+            for pair in produced {
+                matrix[index[pair]][from] += 1;
+            }
+        }
+
+        let initial_counts = pairs
+            .iter()
+            .map(|pair| polymer.pairs.get(pair).copied().unwrap_or(0))
+            .collect();
+
+        PairTransitions {
+            pairs,
+            matrix,
+            initial_counts,
+            last: polymer.last,
+        }
+    }
+
+    /// Each element is counted as the first of a pair, apart from the last element, which isn't the first of
+    /// anything. Nothing needs dividing, so this works in modular arithmetic too.
+    fn element_counts_after<T: Clone + From<u8>>(
+        &self,
+        steps: u64,
+        convert: impl Fn(u64) -> T,
+        add: impl Fn(T, &T) -> T,
+        mul_add: impl Fn(T, &T, &T) -> T,
+    ) -> HashMap<char, T> {
+        let mut power: Matrix<T> = self
+            .matrix
+            .iter()
+            .map(|row| row.iter().map(|&entry| T::from(entry)).collect())
+            .collect();
+        let mut counts: Matrix<T> = self
+            .initial_counts
+            .iter()
+            .map(|&count| vec![convert(count)])
+            .collect();
+
+        let mut remaining = steps;
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                counts = multiply(&power, &counts, &mul_add);
+            }
+            remaining >>= 1;
+            if remaining > 0 {
+                power = multiply(&power, &power, &mul_add);
+            }
+        }
+
+        let mut element_counts: HashMap<char, T> = HashMap::new();
+
+        for (pair, count) in self.pairs.iter().zip(counts) {
+            let total = element_counts.remove(&pair.0).unwrap_or_else(|| T::from(0));
+            element_counts.insert(pair.0, add(total, &count[0]));
+        }
+
+        if let Some(last) = self.last {
+            let total = element_counts.remove(&last).unwrap_or_else(|| T::from(0));
+            element_counts.insert(last, add(total, &T::from(1)));
+        }
+
+        element_counts
+    }
+
+    fn element_counts_modulo(&self, steps: u64, modulus: u64) -> HashMap<char, u64> {
+        let modulus = modulus as u128;
+
+        self.element_counts_after(
+            steps,
+            |count| count as u128 % modulus,
+            |acc, count| (acc + count) % modulus,
+            |acc, a, b| (acc + a * b) % modulus,
+        )
+        .into_iter()
+        .map(|(element, count)| (element, count as u64))
+        .collect()
+    }
+
+    /// The polymer roughly doubles in length each step, so this is only practical for a few thousand steps.
+    fn element_counts_exact(&self, steps: u64) -> HashMap<char, BigUint> {
+        self.element_counts_after(
+            steps,
+            BigUint::from,
+            |acc, count| acc + count,
+            |acc, a, b| acc + a * b,
+        )
     }
 }
 
@@ -118,13 +369,23 @@ fn main() {
     let (polymer, rules) = data.split_once("\n\n").unwrap();
 
     let mut polymer = BulkPolymer::from(polymer.to_string());
-    let rules = PolymerisationRules::from(rules.to_string());
+    let rules = PolymerisationRules::try_from(rules.to_string()).unwrap();
+
+    let missing_rules = polymer.missing_rules(&rules);
+    if !missing_rules.is_empty() {
+        println!("No rules for pairs: {:?}", missing_rules);
+    }
+
+    for steps in [10, 30] {
+        polymer.step_times(&rules, steps);
 
-    polymer.step_times(&rules, 10);
-    polymer.count_elements();
+        let (min_element, min_count) = polymer.least_common_element().unwrap();
+        let (max_element, max_count) = polymer.most_common_element().unwrap();
 
-    polymer.step_times(&rules, 30);
-    polymer.count_elements();
+        println!("Min: {} x {}", min_element, min_count);
+        println!("max: {} x {}", max_element, max_count);
+        println!("Difference: {}", polymer.most_minus_least_common());
+    }
 }
 
 #[cfg(test)]
@@ -138,14 +399,14 @@ mod tests {
         expected_rules.insert(('c', 'a'), 'b');
 
         assert_eq!(
-            PolymerisationRules::from("ab -> c\nca -> b".to_string()),
-            PolymerisationRules(expected_rules)
+            PolymerisationRules::try_from("ab -> c\nca -> b".to_string()),
+            Ok(PolymerisationRules(expected_rules))
         );
     }
 
     #[test]
     fn test_example() {
-        let rules = PolymerisationRules::from(
+        let rules = PolymerisationRules::try_from(
             "CH -> B
 HH -> N
 CB -> H
@@ -163,7 +424,8 @@ BC -> B
 CC -> N
 CN -> C"
                 .to_string(),
-        );
+        )
+        .unwrap();
         let mut polymer = Polymer("NNCB".chars().collect());
 
         polymer.step(&rules);
@@ -171,6 +433,12 @@ CN -> C"
 
         polymer.step(&rules);
         assert_eq!(polymer, Polymer("NBCCNBBBCBHCB".chars().collect()));
+
+        polymer.step_times(&rules, 2);
+        assert_eq!(
+            polymer.to_string(),
+            "NBBNBNBBCCNBCNCCNBBNBBNBBBNBBNBBCBHCBHHNHCBBCBHCB"
+        );
     }
 
     #[test]
@@ -181,7 +449,231 @@ CN -> C"
 
         assert_eq!(
             BulkPolymer::from("abc".to_string()),
-            BulkPolymer(expected_polymer)
+            BulkPolymer {
+                pairs: expected_polymer,
+                first: Some('a'),
+                last: Some('c'),
+            }
         );
     }
+
+    #[test]
+    fn test_element_counts() {
+        let rules = PolymerisationRules::try_from(
+            "CH -> B
+HH -> N
+CB -> H
+NH -> C
+HB -> C
+HC -> B
+HN -> C
+NN -> C
+BH -> H
+NC -> B
+NB -> B
+BN -> B
+BB -> N
+BC -> B
+CC -> N
+CN -> C"
+                .to_string(),
+        )
+        .unwrap();
+        let mut polymer = BulkPolymer::from("NNCB".to_string());
+
+        polymer.step_times(&rules, 2);
+
+        let expected: HashMap<char, u64> = [('N', 2), ('B', 6), ('C', 4), ('H', 1)]
+            .into_iter()
+            .collect();
+        assert_eq!(polymer.element_counts(), expected);
+
+        polymer.step_times(&rules, 8);
+
+        assert_eq!(polymer.most_common_element(), Some(('B', 1749)));
+        assert_eq!(polymer.least_common_element(), Some(('H', 161)));
+        assert_eq!(polymer.most_minus_least_common(), 1588);
+
+        polymer.step_times(&rules, 30);
+
+        assert_eq!(polymer.most_common_element(), Some(('B', 2192039569602)));
+        assert_eq!(polymer.least_common_element(), Some(('H', 3849876073)));
+    }
+
+    #[test]
+    fn test_short_polymer_counts() {
+        let polymer = BulkPolymer::from("A".to_string());
+        assert_eq!(polymer.element_counts(), [('A', 1)].into_iter().collect());
+
+        let polymer = BulkPolymer::from("".to_string());
+        assert_eq!(polymer.element_counts(), HashMap::new());
+        assert_eq!(polymer.most_minus_least_common(), 0);
+    }
+
+    #[test]
+    fn test_pair_transitions() {
+        let rules = PolymerisationRules::try_from(
+            "CH -> B
+HH -> N
+CB -> H
+NH -> C
+HB -> C
+HC -> B
+HN -> C
+NN -> C
+BH -> H
+NC -> B
+NB -> B
+BN -> B
+BB -> N
+BC -> B
+CC -> N
+CN -> C"
+                .to_string(),
+        )
+        .unwrap();
+        let mut polymer = BulkPolymer::from("NNCB".to_string());
+        let transitions = PairTransitions::new(&polymer, &rules);
+
+        polymer.step_times(&rules, 40);
+
+        let exact = transitions.element_counts_exact(40);
+        for (element, count) in polymer.element_counts() {
+            assert_eq!(exact[&element], BigUint::from(count));
+        }
+
+        let modulus = 1_000_000_007;
+        let modular = transitions.element_counts_modulo(40, modulus);
+        for (element, count) in polymer.element_counts() {
+            assert_eq!(modular[&element], count % modulus);
+        }
+
+        let total_length = exact.values().sum::<BigUint>();
+        assert_eq!(
+            total_length,
+            BigUint::from(3u8) * BigUint::from(2u8).pow(40) + 1u8
+        );
+
+        let long_exact = transitions.element_counts_exact(1000);
+        let long_modular = transitions.element_counts_modulo(1000, modulus);
+        assert_eq!(long_modular.len(), 4);
+        for (element, count) in long_exact {
+            assert_eq!(
+                BigUint::from(long_modular[&element]),
+                count % BigUint::from(modulus)
+            );
+        }
+
+        // Every pair has a rule, so each step doubles the number of pairs, and the length is 3·2^steps + 1.
+        let steps = 1_000_000_000_000_000u64;
+        let far_future = transitions.element_counts_modulo(steps, modulus);
+        let big_modulus = BigUint::from(modulus);
+        let expected_length = (BigUint::from(3u8)
+            * BigUint::from(2u8).modpow(&BigUint::from(steps), &big_modulus)
+            + 1u8)
+            % &big_modulus;
+        assert_eq!(
+            BigUint::from(far_future.values().sum::<u64>()) % &big_modulus,
+            expected_length
+        );
+    }
+
+    #[test]
+    fn test_malformed_rules() {
+        assert_eq!(
+            PolymerisationRules::try_from("ab -> c\nabc -> d".to_string()),
+            Err(MalformedRule {
+                line_number: 2,
+                line: "abc -> d".to_string()
+            })
+        );
+        assert!(PolymerisationRules::try_from("ab -> ".to_string()).is_err());
+        assert!(PolymerisationRules::try_from("ab => c".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_missing_rules() {
+        let rules = PolymerisationRules::try_from("AB -> C\nAC -> A".to_string()).unwrap();
+
+        let mut polymer = Polymer("ABB".chars().collect());
+        polymer.step(&rules);
+        assert_eq!(polymer, Polymer("ACBB".chars().collect()));
+        polymer.step(&rules);
+        assert_eq!(polymer, Polymer("AACBB".chars().collect()));
+
+        let mut bulk_polymer = BulkPolymer::from("ABB".to_string());
+        assert_eq!(
+            bulk_polymer.missing_rules(&rules),
+            vec![('A', 'A'), ('B', 'B'), ('C', 'B')]
+        );
+
+        bulk_polymer.step_times(&rules, 2);
+        assert_eq!(
+            bulk_polymer.element_counts(),
+            [('A', 2), ('B', 2), ('C', 1)].into_iter().collect()
+        );
+
+        let transitions = PairTransitions::new(&BulkPolymer::from("ABB".to_string()), &rules);
+        assert_eq!(
+            transitions.element_counts_modulo(2, 1_000_000_007),
+            [('A', 2), ('B', 2), ('C', 1)].into_iter().collect()
+        );
+    }
+
+    /// A xorshift generator, so that the differential test is reproducible without any extra dependencies.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+
+        fn element(&mut self, alphabet: &[char]) -> char {
+            alphabet[self.next(alphabet.len())]
+        }
+    }
+
+    #[test]
+    fn test_bulk_polymer_matches_reference() {
+        let mut random = Random(0x2021_1214);
+
+        for _ in 0..200 {
+            let alphabet = &['A', 'B', 'C', 'D', 'E'][..1 + random.next(5)];
+
+            let template: String = (0..1 + random.next(8))
+                .map(|_| random.element(alphabet))
+                .collect();
+
+            let mut rules = HashMap::new();
+            for &a in alphabet {
+                for &b in alphabet {
+                    // Leave some pairs without a rule.
+                    if random.next(4) != 0 {
+                        rules.insert((a, b), random.element(alphabet));
+                    }
+                }
+            }
+            let rules = PolymerisationRules(rules);
+
+            let steps = random.next(8) as u32;
+
+            let mut reference = Polymer::from(template.as_str());
+            reference.step_times(&rules, steps);
+
+            let mut polymer = BulkPolymer::from(template.clone());
+            polymer.step_times(&rules, steps);
+
+            assert_eq!(
+                polymer.element_counts(),
+                reference.element_counts(),
+                "template {} after {} steps with {:?}",
+                template,
+                steps,
+                rules
+            );
+        }
+    }
 }
@@ -1,3 +1,12 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref TARGET_REGEX: Regex =
+        Regex::new(r"^target area: x=(-?\d+)\.\.(-?\d+), y=(-?\d+)\.\.(-?\d+)$").unwrap();
+}
+
+#[derive(Debug, PartialEq)]
 struct TargetRegion {
     max_x: i32,
     min_x: i32,
@@ -5,21 +14,51 @@ struct TargetRegion {
     min_y: i32,
 }
 
-impl TargetRegion {
-    fn max_y_initial_velocity(&self) -> i32 {
-        -self.min_y - 1
-    }
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Trajectory {
+    x_velocity: i32,
+    y_velocity: i32,
+    peak_height: i32,
+}
+
+#[derive(Debug, PartialEq)]
+enum SolverError {
+    /// The target spans y=0, and some launch leaves the probe stopped horizontally inside it. Every upward
+    /// launch comes back down through y=0, so infinitely many velocities hit.
+    Unbounded,
+}
 
-    fn min_y_initial_velocity(&self) -> i32 {
-        self.min_y
+impl TargetRegion {
+    /// Drag only ever slows the probe, so it can't reach further horizontally than its first step takes it.
+    fn x_velocity_range(&self) -> std::ops::RangeInclusive<i32> {
+        self.min_x.min(0)..=self.max_x.max(0)
     }
 
-    fn max_x_initial_velocity(&self) -> i32 {
-        self.max_x
+    /// Where the probe comes to a stop horizontally.
+    fn x_stopping_point(x_velocity: i32) -> i32 {
+        x_velocity * (x_velocity.abs() + 1) / 2
     }
 
-    fn min_x_initial_velocity(&self) -> i32 {
-        ((self.min_x * 2) as f64).sqrt() as i32
+    fn y_velocity_range(&self) -> Result<std::ops::RangeInclusive<i32>, SolverError> {
+        if self.max_y < 0 {
+            // A probe launched upwards comes back to y=0 moving one faster than it was launched, so it must
+            // not then skip straight past the bottom of the target.
+            Ok(self.min_y..=-self.min_y - 1)
+        } else if self.min_y > 0 {
+            // The way down passes through the same heights as the way up, so the first step mustn't
+            // overshoot the top of the target.
+            Ok(1..=self.max_y)
+        } else if self
+            .x_velocity_range()
+            .any(|x_velocity| self.contains(Self::x_stopping_point(x_velocity), 0))
+        {
+            Err(SolverError::Unbounded)
+        } else {
+            // Every hit happens while the probe is still moving horizontally, so within the first
+            // `max_steps` steps. Any faster launch is still above the target by then.
+            let max_steps = self.min_x.abs().max(self.max_x.abs());
+            Ok(self.min_y..=self.max_y + max_steps)
+        }
     }
 
     fn contains(&self, x: i32, y: i32) -> bool {
@@ -30,7 +69,8 @@ impl TargetRegion {
         let mut x = 0;
         let mut y = 0;
 
-        while y >= self.min_y {
+        // Once the probe is below the target and not rising, it can never come back up.
+        while y >= self.min_y || y_velocity > 0 {
             x += x_velocity;
             y += y_velocity;
 
@@ -38,44 +78,315 @@ impl TargetRegion {
                 return true;
             }
 
-            if x_velocity > 0 {
-                x_velocity -= 1;
-            }
+            x_velocity -= x_velocity.signum();
             y_velocity -= 1;
         }
 
         false
     }
 
-    fn get_count_of_intersecting_trajectories(&self) -> usize {
-        (self.min_x_initial_velocity()..=self.max_x_initial_velocity())
-            .map(|x_velocity| {
-                (self.min_y_initial_velocity()..=self.max_y_initial_velocity())
-                    .filter(|y_velocity| self.trajectory_intersects(x_velocity, y_velocity.clone()))
-                    .count()
+    /// Every position the probe passes through, stopping once it's inside the target or can no longer reach
+    /// it.
+    #[allow(dead_code)]
+    fn trace(&self, mut x_velocity: i32, mut y_velocity: i32) -> Vec<(i32, i32)> {
+        let mut positions = vec![];
+        let mut x = 0;
+        let mut y = 0;
+
+        while y >= self.min_y || y_velocity > 0 {
+            x += x_velocity;
+            y += y_velocity;
+            positions.push((x, y));
+
+            if self.contains(x, y) {
+                break;
+            }
+
+            x_velocity -= x_velocity.signum();
+            y_velocity -= 1;
+        }
+
+        positions
+    }
+
+    /// Draws the trajectory in the same style as the puzzle: `S` for the launcher, `#` for the probe and `T`
+    /// for the target.
+    #[allow(dead_code)]
+    fn plot(&self, x_velocity: i32, y_velocity: i32) -> String {
+        let positions = self.trace(x_velocity, y_velocity);
+        let xs = positions
+            .iter()
+            .map(|p| p.0)
+            .chain([0, self.min_x, self.max_x]);
+        let ys = positions
+            .iter()
+            .map(|p| p.1)
+            .chain([0, self.min_y, self.max_y]);
+
+        let (left, right) = (xs.clone().min().unwrap(), xs.max().unwrap());
+        let (bottom, top) = (ys.clone().min().unwrap(), ys.max().unwrap());
+
+        let mut plot = String::new();
+
+        for y in (bottom..=top).rev() {
+            for x in left..=right {
+                plot.push(if (x, y) == (0, 0) {
+                    'S'
+                } else if positions.contains(&(x, y)) {
+                    '#'
+                } else if self.contains(x, y) {
+                    'T'
+                } else {
+                    '.'
+                });
+            }
+            plot.push('\n');
+        }
+
+        plot
+    }
+
+    fn solve(&self) -> Result<Vec<Trajectory>, SolverError> {
+        let y_velocities = self.y_velocity_range()?;
+
+        Ok(self
+            .x_velocity_range()
+            .flat_map(|x_velocity| {
+                y_velocities
+                    .clone()
+                    .filter(move |&y_velocity| self.trajectory_intersects(x_velocity, y_velocity))
+                    .map(move |y_velocity| Trajectory {
+                        x_velocity,
+                        y_velocity,
+                        peak_height: y_velocity.max(0) * (y_velocity.max(0) + 1) / 2,
+                    })
             })
-            .sum()
+            .collect())
+    }
+
+    fn best_trajectory(&self) -> Result<Option<Trajectory>, SolverError> {
+        Ok(self
+            .solve()?
+            .into_iter()
+            .max_by_key(|t| (t.peak_height, t.y_velocity, -t.x_velocity.abs())))
+    }
+
+    /// The steps at which a probe with this x velocity is within the target's x range, up to `max_step`.
+    /// x only ever moves one way, so these are always a single run of consecutive steps.
+    fn x_step_run(&self, x_velocity: i32, max_step: u32) -> Option<(u32, u32)> {
+        let in_range = |x| self.min_x <= x && x <= self.max_x;
+
+        let mut x = 0;
+        let mut velocity = x_velocity;
+        let mut run: Option<(u32, u32)> = None;
+
+        for step in 1..=max_step {
+            x += velocity;
+            velocity -= velocity.signum();
+
+            if in_range(x) {
+                let first = run.map_or(step, |(first, _)| first);
+                run = Some((first, step));
+
+                if velocity == 0 {
+                    // Stopped inside the target, so it stays there for good.
+                    return Some((first, max_step));
+                }
+            } else {
+                // Once outside, the probe is done if it has already passed through, or is stopped or moving
+                // away on either side.
+                let moving_away =
+                    (x > self.max_x && velocity >= 0) || (x < self.min_x && velocity <= 0);
+                if run.is_some() || moving_away {
+                    break;
+                }
+            }
+        }
+
+        run
+    }
+
+    /// The steps at which a probe with this y velocity is within the target's y range.
+    fn y_steps(&self, y_velocity: i32) -> Vec<u32> {
+        let in_range = |y| self.min_y <= y && y <= self.max_y;
+        let mut steps = vec![];
+
+        if y_velocity <= 0 {
+            let mut y = 0;
+            let mut velocity = y_velocity;
+            let mut step = 0;
+
+            while y >= self.min_y {
+                step += 1;
+                y += velocity;
+                velocity -= 1;
+
+                if in_range(y) {
+                    steps.push(step);
+                }
+            }
+
+            return steps;
+        }
+
+        // On the way up, stop as soon as the probe is above the target.
+        let mut y = 0;
+        for step in 1..=y_velocity as u32 {
+            y += y_velocity - (step as i32 - 1);
+            if y > self.max_y {
+                break;
+            }
+            if in_range(y) {
+                steps.push(step);
+            }
+        }
+
+        // The way down mirrors the way up, back to y=0 at step 2v+1. From there it's as though the probe had
+        // been launched downwards at v+1.
+        let return_step = 2 * y_velocity as u32 + 1;
+        let descending: Vec<u32> = steps.iter().rev().map(|step| return_step - step).collect();
+        steps.extend(descending);
+
+        if in_range(0) {
+            steps.push(return_step);
+        }
+
+        steps.extend(
+            self.y_steps(-y_velocity - 1)
+                .into_iter()
+                .map(|step| return_step + step),
+        );
+
+        steps
+    }
+
+    /// Counts trajectories without simulating every pair of velocities. Each axis is solved on its own for
+    /// the steps at which it's inside the target, and a pair of velocities hits if those steps overlap.
+    fn count_by_step_sets(&self) -> Result<usize, SolverError> {
+        let y_runs: Vec<Vec<(u32, u32)>> = self
+            .y_velocity_range()?
+            .map(|y_velocity| {
+                let mut runs: Vec<(u32, u32)> = vec![];
+
+                for step in self.y_steps(y_velocity) {
+                    match runs.last_mut() {
+                        Some(run) if run.1 + 1 == step => run.1 = step,
+                        _ => runs.push((step, step)),
+                    }
+                }
+
+                runs
+            })
+            .filter(|runs| !runs.is_empty())
+            .collect();
+
+        let max_step = match y_runs
+            .iter()
+            .filter_map(|runs| runs.last())
+            .map(|run| run.1)
+            .max()
+        {
+            Some(max_step) => max_step,
+            None => return Ok(0),
+        };
+
+        let mut x_runs: Vec<(u32, u32)> = self
+            .x_velocity_range()
+            .filter_map(|x_velocity| self.x_step_run(x_velocity, max_step))
+            .collect();
+        x_runs.sort_unstable();
+
+        let firsts: Vec<u32> = x_runs.iter().map(|run| run.0).collect();
+        let mut lasts: Vec<u32> = x_runs.iter().map(|run| run.1).collect();
+        lasts.sort_unstable();
+
+        Ok(y_runs
+            .iter()
+            .map(|runs| {
+                let (earliest, latest) = (runs[0].0, runs[runs.len() - 1].1);
+
+                // x runs that miss every y run either finish too early, start too late, or fall into a gap.
+                let too_early = lasts.partition_point(|&last| last < earliest);
+                let too_late = firsts.len() - firsts.partition_point(|&first| first <= latest);
+                let in_gaps: usize = runs
+                    .windows(2)
+                    .map(|pair| {
+                        let (gap_start, gap_end) = (pair[0].1 + 1, pair[1].0 - 1);
+                        let starts_in_gap = firsts.partition_point(|&first| first < gap_start)
+                            ..firsts.partition_point(|&first| first <= gap_end);
+
+                        x_runs[starts_in_gap]
+                            .iter()
+                            .filter(|run| run.1 <= gap_end)
+                            .count()
+                    })
+                    .sum();
+
+                x_runs.len() - too_early - too_late - in_gaps
+            })
+            .sum())
+    }
+
+    fn get_count_of_intersecting_trajectories(&self) -> Result<usize, SolverError> {
+        self.count_by_step_sets()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum TargetParseError {
+    Malformed(String),
+    /// The range for the named axis has its minimum above its maximum.
+    EmptyRange(char),
+}
+
+impl TryFrom<&str> for TargetRegion {
+    type Error = TargetParseError;
+
+    fn try_from(input: &str) -> Result<TargetRegion, TargetParseError> {
+        let captures = TARGET_REGEX
+            .captures(input.trim())
+            .ok_or_else(|| TargetParseError::Malformed(input.to_string()))?;
+        let bound = |group: usize| {
+            captures[group]
+                .parse::<i32>()
+                .map_err(|_| TargetParseError::Malformed(input.to_string()))
+        };
+
+        let target_region = TargetRegion {
+            min_x: bound(1)?,
+            max_x: bound(2)?,
+            min_y: bound(3)?,
+            max_y: bound(4)?,
+        };
+
+        if target_region.min_x > target_region.max_x {
+            return Err(TargetParseError::EmptyRange('x'));
+        }
+        if target_region.min_y > target_region.max_y {
+            return Err(TargetParseError::EmptyRange('y'));
+        }
+
+        Ok(target_region)
     }
 }
 
 fn main() {
-    let target_region = TargetRegion {
-        max_x: 161,
-        min_x: 111,
-        max_y: -101,
-        min_y: -154,
-    };
+    let data = std::fs::read_to_string("data/day17.txt").unwrap();
+    let target_region = TargetRegion::try_from(data.as_str()).unwrap();
 
-    // The region is below the y axis, so the solution is for y to be such that on the return
-    // it jumps straight from 0 to -154.
-    println!(
-        "Analytic solution for part 1: {}",
-        (target_region.max_y_initial_velocity() * (target_region.max_y_initial_velocity() + 1)) / 2
-    );
+    match target_region.best_trajectory().unwrap() {
+        Some(best) => println!(
+            "Highest trajectory: {} (launched at {},{})",
+            best.peak_height, best.x_velocity, best.y_velocity
+        ),
+        None => println!("No trajectory reaches the target"),
+    }
 
     println!(
         "Total intersecting trajectories: {}",
-        target_region.get_count_of_intersecting_trajectories()
+        target_region
+            .get_count_of_intersecting_trajectories()
+            .unwrap()
     );
 }
 
@@ -92,12 +403,267 @@ mod tests {
 
     #[test]
     fn test_trajectory() {
-        assert_eq!(EXAMPLE.trajectory_intersects(6, 3), true);
-        assert_eq!(EXAMPLE.trajectory_intersects(23, -4), false);
+        assert!(EXAMPLE.trajectory_intersects(6, 3));
+        assert!(!EXAMPLE.trajectory_intersects(23, -4));
     }
 
     #[test]
     fn test_all_trajectories() {
-        assert_eq!(EXAMPLE.get_count_of_intersecting_trajectories(), 112);
+        assert_eq!(EXAMPLE.get_count_of_intersecting_trajectories(), Ok(112));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            TargetRegion::try_from("target area: x=20..30, y=-10..-5"),
+            Ok(EXAMPLE)
+        );
+        assert_eq!(
+            TargetRegion::try_from("target area: x=-30..-20, y=-10..-5\n"),
+            Ok(TargetRegion {
+                max_x: -20,
+                min_x: -30,
+                max_y: -5,
+                min_y: -10,
+            })
+        );
+        assert_eq!(
+            TargetRegion::try_from("target area: x=30..20, y=-10..-5"),
+            Err(TargetParseError::EmptyRange('x'))
+        );
+        assert_eq!(
+            TargetRegion::try_from("target area: x=20..30, y=-5..-10"),
+            Err(TargetParseError::EmptyRange('y'))
+        );
+        assert_eq!(
+            TargetRegion::try_from("target area: x=20..30"),
+            Err(TargetParseError::Malformed(
+                "target area: x=20..30".to_string()
+            ))
+        );
+    }
+
+    /// Checks the solver against a brute-force search over a generous box of velocities.
+    fn assert_matches_brute_force(target_region: &TargetRegion) {
+        let mut expected = vec![];
+        for x_velocity in -100..=100 {
+            for y_velocity in -100..=200 {
+                if target_region.trajectory_intersects(x_velocity, y_velocity) {
+                    expected.push((x_velocity, y_velocity));
+                }
+            }
+        }
+
+        let actual: Vec<_> = target_region
+            .solve()
+            .unwrap()
+            .into_iter()
+            .map(|t| (t.x_velocity, t.y_velocity))
+            .collect();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_best_trajectory() {
+        assert_eq!(
+            EXAMPLE.best_trajectory(),
+            Ok(Some(Trajectory {
+                x_velocity: 6,
+                y_velocity: 9,
+                peak_height: 45
+            }))
+        );
+    }
+
+    #[test]
+    fn test_other_quadrants() {
+        let behind = TargetRegion {
+            max_x: -20,
+            min_x: -30,
+            max_y: -5,
+            min_y: -10,
+        };
+        assert_eq!(behind.get_count_of_intersecting_trajectories(), Ok(112));
+        assert_eq!(behind.best_trajectory().unwrap().unwrap().peak_height, 45);
+        assert_matches_brute_force(&behind);
+
+        let above = TargetRegion {
+            max_x: 30,
+            min_x: 20,
+            max_y: 10,
+            min_y: 5,
+        };
+        assert!(above.trajectory_intersects(6, 5));
+        assert_eq!(above.best_trajectory().unwrap().unwrap().y_velocity, 10);
+        assert_matches_brute_force(&above);
+
+        let straddling_x = TargetRegion {
+            max_x: 5,
+            min_x: -5,
+            max_y: -5,
+            min_y: -10,
+        };
+        assert_matches_brute_force(&straddling_x);
+
+        let straddling_y = TargetRegion {
+            max_x: 27,
+            min_x: 22,
+            max_y: 5,
+            min_y: -5,
+        };
+        assert_matches_brute_force(&straddling_y);
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let target_region = TargetRegion {
+            max_x: 30,
+            min_x: 20,
+            max_y: 5,
+            min_y: -5,
+        };
+
+        assert!(target_region.trajectory_intersects(6, 50));
+        assert_eq!(target_region.solve(), Err(SolverError::Unbounded));
+    }
+
+    #[test]
+    fn test_step_sets_match_simulation() {
+        let target_regions = [
+            EXAMPLE,
+            TargetRegion {
+                max_x: -20,
+                min_x: -30,
+                max_y: -5,
+                min_y: -10,
+            },
+            TargetRegion {
+                max_x: 30,
+                min_x: 20,
+                max_y: 10,
+                min_y: 5,
+            },
+            TargetRegion {
+                max_x: 5,
+                min_x: -5,
+                max_y: -5,
+                min_y: -10,
+            },
+            TargetRegion {
+                max_x: 27,
+                min_x: 22,
+                max_y: 5,
+                min_y: -5,
+            },
+            TargetRegion {
+                max_x: 200,
+                min_x: 100,
+                max_y: -50,
+                min_y: -150,
+            },
+        ];
+
+        for target_region in target_regions {
+            assert_eq!(
+                target_region.count_by_step_sets(),
+                Ok(target_region.solve().unwrap().len()),
+                "{:?}",
+                target_region
+            );
+        }
+    }
+
+    #[test]
+    fn test_wide_target() {
+        let target_region = TargetRegion {
+            max_x: 15000,
+            min_x: 10000,
+            max_y: -3000,
+            min_y: -5000,
+        };
+
+        let start = std::time::Instant::now();
+        let count = target_region.count_by_step_sets().unwrap();
+
+        assert!(count > 5001 * 2001);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_trace() {
+        assert_eq!(
+            EXAMPLE.trace(7, 2),
+            vec![
+                (7, 2),
+                (13, 3),
+                (18, 3),
+                (22, 2),
+                (25, 0),
+                (27, -3),
+                (28, -7)
+            ]
+        );
+        assert_eq!(EXAMPLE.trace(17, -4), vec![(17, -4), (33, -9), (48, -15)]);
+    }
+
+    #[test]
+    fn test_plot() {
+        assert_eq!(
+            EXAMPLE.plot(7, 2),
+            ".............#....#............
+.......#..............#........
+...............................
+S........................#.....
+...............................
+...............................
+...........................#...
+...............................
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTT#TT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+"
+        );
+
+        assert_eq!(
+            EXAMPLE.plot(6, 3),
+            "...............#..#............
+...........#........#..........
+...............................
+......#..............#.........
+...............................
+...............................
+S....................#.........
+...............................
+...............................
+...............................
+.....................#.........
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................T#TTTTTTTTT
+....................TTTTTTTTTTT
+"
+        );
+
+        assert_eq!(
+            EXAMPLE.plot(9, 0),
+            "S........#.....................
+.................#.............
+...............................
+........................#......
+...............................
+....................TTTTTTTTTTT
+....................TTTTTTTTTT#
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+....................TTTTTTTTTTT
+"
+        );
     }
 }